@@ -1,6 +1,7 @@
 #[macro_use] extern crate nickel;
 use async_trait::async_trait;
 use nickel::{Nickel, HttpRouter, Request, Response, Middleware, MiddlewareResult};
+use nickel::multipart::MultipartOptions;
 use std::collections::HashMap;
 
 struct Root;
@@ -36,6 +37,35 @@ impl Middleware<()> for Confirmation {
     }
 }
 
+struct Upload;
+
+#[async_trait]
+impl Middleware<()> for Upload {
+    async fn invoke(&self, req: &mut Request, res: Response) -> MiddlewareResult {
+
+        // Keep uploads small, as every file is written to disk
+        let options = MultipartOptions::default()
+            .part_limit(Some(1024 * 1024))
+            .total_limit(Some(4 * 1024 * 1024));
+        let mut form = try_with!(res, req.multipart_with(options));
+
+        let mut uploaded = Vec::new();
+        while let Some(part) = try_with!(res, form.next_part().await) {
+            if part.is_file() {
+                // Spooled to a temporary file, which is removed when dropped.
+                // Use `persist` to keep it.
+                let file = try_with!(res, part.spool().await);
+                uploaded.push(format!("{} ({} bytes)", file.file_name().unwrap_or("unnamed"), file.size()));
+            }
+        }
+
+        let mut data = HashMap::new();
+        data.insert("title", "Uploaded".to_string());
+        data.insert("files", uploaded.join(", "));
+        return res.render("examples/form_data/views/uploaded.html", &data).await
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let mut server = Nickel::new();
@@ -44,5 +74,7 @@ async fn main() {
 
     server.post("/confirmation", Confirmation);
 
+    server.post("/upload", Upload);
+
     server.listen("0.0.0.0:8080").await.unwrap();
 }
@@ -1,13 +1,22 @@
 #[cfg(feature = "ssl")]
-fn main() {
-    use hyper::net::Openssl;
+#[tokio::main]
+async fn main() {
+    use nickel::{Nickel, HttpRouter, Options, Request, Response, MiddlewareResult, TlsConfig};
 
-    use nickel::{Nickel, HttpRouter};
+    fn hello(req: &mut Request, res: Response) -> MiddlewareResult {
+        if req.is_secure() {
+            res.send("Hello World from HTTPS")
+        } else {
+            res.send("Hello World from plain HTTP?!")
+        }
+    }
 
-    let ssl = Openssl::with_cert_and_key("examples/assets/self_signed.crt", "examples/assets/key.pem").unwrap();
+    let tls = TlsConfig::from_pem_files("examples/assets/self_signed.crt", "examples/assets/key.pem").unwrap();
     let mut server = Nickel::new();
-    server.get("**", middleware!("Hello World from HTTPS"));
-    server.listen_https("127.0.0.1:6767", ssl).unwrap();
+    // Offer HTTP/2 to clients that negotiate it with ALPN
+    server.options = Options::default().http2(true);
+    server.get("**", hello);
+    server.listen_https("127.0.0.1:6767", tls).await.unwrap();
 }
 
 #[cfg(not(feature = "ssl"))]
@@ -4,8 +4,7 @@
 extern crate nickel;
 
 use serde_json;
-use async_trait::async_trait;
-use nickel::{Nickel, HttpRouter, Request, Response, Middleware, MiddlewareResult};
+use nickel::{Nickel, HttpRouter, Request, Response, MiddlewareResult};
 use serde_derive::{Serialize, Deserialize};
 use std::error::Error as StdError;
 use std::{env, str};
@@ -49,33 +48,28 @@ impl ServerData {
     }
 }
 
-struct JsonPost;
-
-#[async_trait]
-impl Middleware<ServerData> for JsonPost {
-    async fn invoke(&self, req: &mut Request<ServerData>, res: Response<ServerData>) -> MiddlewareResult<ServerData> {
-        #[derive(Serialize, Deserialize)]
-        struct Data { name: String, age: Option<u32> }
-
-        let client = try_with!(res, req.json_as::<Data>().await);
-
-        let msg = match client.age {
-            Some(age) => {
-                serde_json::from_str::<serde_json::Value>(&format!(
-                    r#"{{ "message": "Hello {}, your age is {}" }}"#,
-                    client.name,
-                    age
-                )).unwrap()
-            }
-            None => {
-                serde_json::from_str::<serde_json::Value>(&format!(
-                    r#"{{ "message": "Hello {}, I don't know your age" }}"#,
-                    client.name
-                )).unwrap()
-            }
-        };
-        res.send(msg)
-    }
+async fn json_post(req: &mut Request<ServerData>, res: Response<ServerData>) -> MiddlewareResult<ServerData> {
+    #[derive(Serialize, Deserialize)]
+    struct Data { name: String, age: Option<u32> }
+
+    let client = try_with!(res, req.json_as::<Data>().await);
+
+    let msg = match client.age {
+        Some(age) => {
+            serde_json::from_str::<serde_json::Value>(&format!(
+                r#"{{ "message": "Hello {}, your age is {}" }}"#,
+                client.name,
+                age
+            )).unwrap()
+        }
+        None => {
+            serde_json::from_str::<serde_json::Value>(&format!(
+                r#"{{ "message": "Hello {}, I don't know your age" }}"#,
+                client.name
+            )).unwrap()
+        }
+    };
+    res.send(msg)
 }
 
 #[tokio::main]
@@ -117,7 +111,7 @@ where D: Database {
     });
 
     // Json example
-    server.post("/", JsonPost);
+    server.post("/", json_post);
     // TODO: the middleware macro has not yet been updated to support async    
     // server.post("/", middleware! { |req, res|
     //     #[derive(Serialize, Deserialize)]
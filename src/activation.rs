@@ -0,0 +1,93 @@
+//! Socket activation, where a supervisor such as systemd binds the listening
+//! socket and passes it to the server on startup.
+use std::io;
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::{env, process};
+#[cfg(unix)]
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// File descriptors passed by the supervisor start here, after stdio.
+#[cfg(unix)]
+const LISTEN_FDS_START: RawFd = 3;
+
+// Set once the passed descriptors have been wrapped, so they're never owned
+// twice.
+#[cfg(unix)]
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Take the TCP listeners passed to this process using the systemd
+/// `LISTEN_FDS` protocol.
+///
+/// Returns an empty `Vec` if no sockets were passed, if they were meant for
+/// another process (`LISTEN_PID` is missing or isn't this process), or if
+/// they were already taken by an earlier call.
+///
+/// The environment is left as it is, since changing it while other threads
+/// may be reading it is a data race. Child processes inherit the variables,
+/// but as their `LISTEN_PID` doesn't match they won't take the sockets.
+///
+/// # Examples
+/// ```rust,no_run
+/// use nickel::Nickel;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let listener = nickel::listen_fds().unwrap().pop()
+///         .expect("expected a socket from the supervisor");
+///     Nickel::new().listen_on(listener).await.unwrap();
+/// }
+/// ```
+#[cfg(unix)]
+pub fn listen_fds() -> io::Result<Vec<TcpListener>> {
+    let count = match passed_fd_count() {
+        Some(count) if !TAKEN.swap(true, Ordering::SeqCst) => count,
+        _ => return Ok(Vec::new()),
+    };
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count).map(|fd| {
+        // Safety: the supervisor hands these descriptors to us, and `TAKEN`
+        // ensures they are only ever wrapped once.
+        let listener = unsafe { TcpListener::from_raw_fd(fd) };
+        match listener.local_addr() {
+            Ok(_) => Ok(listener),
+            Err(e) => {
+                // Not a TCP socket, leave it open for whoever else wants it
+                let _ = listener.into_raw_fd();
+                Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                   format!("passed file descriptor {} is not a TCP socket: {}", fd, e)))
+            }
+        }
+    }).collect()
+}
+
+#[cfg(unix)]
+fn passed_fd_count() -> Option<i32> {
+    let pid = env::var("LISTEN_PID").ok()?;
+    if pid.parse::<u32>().ok() != Some(process::id()) {
+        return None;
+    }
+    env::var("LISTEN_FDS").ok()
+        .and_then(|count| count.parse().ok())
+        .filter(|&count| count > 0)
+}
+
+/// The first socket passed by a supervisor, if any. Used by `Nickel::listen`
+/// in place of binding its own socket.
+#[cfg(unix)]
+pub(crate) fn activated_listener() -> io::Result<Option<TcpListener>> {
+    let mut listeners = listen_fds()?.into_iter();
+    let first = listeners.next();
+    if listeners.next().is_some() {
+        warn!("More than one socket was passed to the server, only the first is used");
+    }
+    Ok(first)
+}
+
+/// Sockets can't be passed to the server on this platform.
+#[cfg(not(unix))]
+pub(crate) fn activated_listener() -> io::Result<Option<TcpListener>> {
+    Ok(None)
+}
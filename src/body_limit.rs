@@ -0,0 +1,125 @@
+use async_trait::async_trait;
+use crate::middleware::{IntoMiddleware, Middleware, MiddlewareResult};
+use crate::request::Request;
+use crate::response::Response;
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// Overrides the maximum request body size for the wrapped handler, in
+/// place of the global `Options::max_body_size`.
+///
+/// # Examples
+/// ```rust
+/// use nickel::{Nickel, HttpRouter, BodyLimit, Request, Response, MiddlewareResult};
+///
+/// fn upload(_req: &mut Request, res: Response) -> MiddlewareResult {
+///     res.send("Thanks!")
+/// }
+///
+/// let mut server = Nickel::new();
+/// // Allow uploads of up to 10MiB on this route only
+/// server.post("/upload", BodyLimit::new(Some(10 * 1024 * 1024), upload));
+/// ```
+pub struct BodyLimit<M, D = ()> {
+    limit: Option<usize>,
+    handler: M,
+    _data: PhantomData<fn() -> D>,
+}
+
+impl<M, D: Send + 'static + Sync> BodyLimit<M, D> {
+    /// Wrap `handler` so requests it handles may have bodies of up to
+    /// `limit` bytes, or any size for `None`.
+    pub fn new<T, K>(limit: Option<usize>, handler: T) -> BodyLimit<M, D>
+    where T: IntoMiddleware<D, K, Middleware = M> {
+        BodyLimit { limit, handler: handler.into_middleware(), _data: PhantomData }
+    }
+}
+
+#[async_trait]
+impl<D, M> Middleware<D> for BodyLimit<M, D>
+where D: Send + 'static + Sync,
+      M: Middleware<D> {
+    async fn invoke(&self, req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        let previous = req.body_limit();
+        req.set_body_limit(self.limit);
+        let result = self.handler.invoke(req, res).await;
+        // later middleware gets the limit it would have had otherwise
+        req.set_body_limit(previous);
+        result
+    }
+}
+
+/// The error a body stream yields once it has exceeded the body size limit.
+#[derive(Debug)]
+pub(crate) struct BodyTooLarge {
+    pub(crate) limit: usize,
+}
+
+impl Error for BodyTooLarge {}
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request body is larger than the limit of {} bytes", self.limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{HttpRouter, Middleware, MiddlewareResult, Nickel, Options, Request, Response};
+    use crate::BodyLimit;
+    use crate::test_support::serve;
+    use async_trait::async_trait;
+    use hyper::StatusCode;
+
+    struct BodyLength;
+
+    #[async_trait]
+    impl Middleware<()> for BodyLength {
+        async fn invoke(&self, req: &mut Request, res: Response) -> MiddlewareResult {
+            let body = try_with!(res, req.raw_body().await);
+            res.send(body.len().to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_are_rejected() {
+        use futures::stream;
+
+        let mut server = Nickel::new();
+        server.options = Options::default().max_body_size(Some(16));
+        server.post("/", BodyLength);
+        server.post("/big", BodyLimit::new(Some(64), BodyLength));
+        let server = serve(server).await;
+
+        assert_eq!(server.post("/", "small").await, (StatusCode::OK, "5".to_string()));
+
+        // rejected from the Content-Length header
+        assert_eq!(server.post("/", vec![b'a'; 32]).await,
+                   (StatusCode::PAYLOAD_TOO_LARGE, "Payload Too Large".to_string()));
+
+        // chunked, so only caught while reading
+        let chunks = (0..3).map(|_| Ok::<_, std::io::Error>(vec![b'a'; 10]));
+        let (status, _) = server.post("/", hyper::Body::wrap_stream(stream::iter(chunks))).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // the per-route override applies
+        assert_eq!(server.post("/big", vec![b'a'; 32]).await, (StatusCode::OK, "32".to_string()));
+        assert_eq!(server.post("/big", vec![b'a'; 65]).await.0, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    fn pass_on(_: &mut Request, res: Response) -> MiddlewareResult {
+        res.next_middleware()
+    }
+
+    #[tokio::test]
+    async fn body_limit_override_ends_with_its_handler() {
+        let mut server = Nickel::new();
+        server.options = Options::default().max_body_size(Some(16));
+        server.utilize(BodyLimit::new(Some(64), pass_on));
+        server.post("/", BodyLength);
+        let server = serve(server).await;
+
+        assert_eq!(server.post("/", vec![b'a'; 32]).await.0, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
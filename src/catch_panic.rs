@@ -0,0 +1,94 @@
+//! Recording handler panics so they can be turned into error responses.
+use std::any::Any;
+use std::cell::RefCell;
+use std::panic;
+use std::sync::Once;
+
+thread_local! {
+    // Where the most recent panic on this thread happened. The panic hook
+    // runs on the panicking thread before unwinding starts, which is the
+    // thread that polls the handler and catches the unwind.
+    static LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+static INSTALL_HOOK: Once = Once::new();
+
+/// Wrap the current panic hook so panic locations are recorded for
+/// `describe`. The previous hook still runs afterwards.
+pub(crate) fn install_hook() {
+    INSTALL_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let location = info.location().map(|l| l.to_string());
+            LOCATION.with(|last| *last.borrow_mut() = location);
+            previous(info);
+        }));
+    });
+}
+
+/// Describe a caught panic by its message and, if known, its location.
+pub(crate) fn describe(payload: &(dyn Any + Send)) -> String {
+    let message = payload.downcast_ref::<&str>().copied()
+        .or_else(|| payload.downcast_ref::<String>().map(|s| &s[..]))
+        .unwrap_or("Box<dyn Any>");
+
+    match LOCATION.with(|last| last.borrow_mut().take()) {
+        Some(location) => format!("Handler panicked at {}: {}", location, message),
+        None => format!("Handler panicked: {}", message),
+    }
+}
+
+#[test]
+fn describes_panic_message_and_location() {
+    install_hook();
+    let payload = panic::catch_unwind(|| panic!("boom {}", 42)).unwrap_err();
+    let description = describe(&*payload);
+    assert!(description.starts_with("Handler panicked at src/catch_panic.rs:"), "{}", description);
+    assert!(description.ends_with(": boom 42"), "{}", description);
+
+    // Without a recorded location there is still the message
+    assert_eq!(describe(&"plain"), "Handler panicked: plain");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Action, Continue, Halt, HttpRouter, Nickel, NickelError, Options, Request};
+    use crate::test_support::{panics, serve};
+    use hyper::{Client, StatusCode};
+
+    #[tokio::test]
+    async fn handler_panics_become_500s() {
+        fn show_message(err: &mut NickelError<()>, _req: &mut Request<()>) -> Action {
+            let message = err.message.to_string();
+            match err.stream {
+                Some(ref mut res) => {
+                    res.set_body(message);
+                    Halt(())
+                }
+                None => Continue(()),
+            }
+        }
+
+        let mut server = Nickel::new();
+        server.handle_error(show_message);
+        server.get("/panic", panics);
+        server.get("/ok", middleware!("ok"));
+        let server = serve(server).await;
+
+        let (status, body) = server.get("/panic").await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(body.starts_with("Handler panicked at src/test_support.rs:"), "{}", body);
+        assert!(body.ends_with(": handler blew up"), "{}", body);
+        assert_eq!(server.get("/ok").await, (StatusCode::OK, "ok".to_string()));
+    }
+
+    #[tokio::test]
+    async fn uncaught_panics_drop_the_connection() {
+        let mut server = Nickel::new();
+        server.options = Options::default().catch_panics(false);
+        server.get("/panic", panics);
+        let server = serve(server).await;
+
+        assert!(Client::new().get(server.url("/panic").parse().unwrap()).await.is_err());
+    }
+}
@@ -0,0 +1,129 @@
+use async_trait::async_trait;
+use crate::middleware::{Continue, Halt, IntoMiddleware, Middleware, MiddlewareResult};
+use crate::request::Request;
+use crate::response::Response;
+
+/// An ordered list of middleware that acts as a single middleware, to
+/// attach several steps to one route or to a group of routes. Usually
+/// built with the `chain!` macro.
+///
+/// Each step runs in turn for as long as they return `Continue`. A step
+/// that returns `Halt` or an error ends the chain with that result, just as
+/// it would in the server's own middleware stack.
+///
+/// A chain added with `utilize` runs its steps for every request that
+/// reaches it, whatever the later steps would match. To share steps between
+/// a group of routes, put the routes in a router and scope the chain with
+/// `when`, so that requests outside the group never reach it.
+///
+/// # Examples
+/// ```rust
+/// #[macro_use] extern crate nickel;
+/// use nickel::{Nickel, HttpRouter, Chain, Request, Response, MiddlewareResult, when};
+/// use nickel::status::StatusCode;
+///
+/// fn auth(req: &mut Request, res: Response) -> MiddlewareResult {
+///     if req.origin.headers().contains_key("authorization") {
+///         res.next_middleware()
+///     } else {
+///         res.error(StatusCode::UNAUTHORIZED, "Login required")
+///     }
+/// }
+///
+/// async fn audit(req: &mut Request, res: Response) -> MiddlewareResult {
+///     println!("admin access to {}", req.path_without_query());
+///     res.next_middleware()
+/// }
+///
+/// fn main() {
+///     let mut server = Nickel::new();
+///     server.get("/admin", chain![auth, audit, middleware!("Welcome")]);
+///
+///     // The same, without the macro
+///     server.get("/admin/users", Chain::new().link(auth).link(audit).link(middleware!("Users")));
+///
+///     // Every route under /reports requires a login
+///     let mut reports = Nickel::router();
+///     reports.get("/reports/sales", middleware!("Sales"));
+///     reports.get("/reports/stock", middleware!("Stock"));
+///     server.utilize(when(|req: &Request| req.path_without_query().starts_with("/reports"),
+///                         chain![auth, reports]));
+/// }
+/// ```
+pub struct Chain<D: Send + 'static + Sync = ()> {
+    steps: Vec<Box<dyn Middleware<D> + Send + Sync>>,
+}
+
+impl<D: Send + 'static + Sync> Chain<D> {
+    /// An empty chain, which passes every request on.
+    pub fn new() -> Chain<D> {
+        Chain { steps: Vec::new() }
+    }
+
+    /// Add a step to the end of the chain.
+    pub fn link<T: IntoMiddleware<D, K>, K>(mut self, middleware: T) -> Chain<D> {
+        self.steps.push(Box::new(middleware.into_middleware()));
+        self
+    }
+}
+
+impl<D: Send + 'static + Sync> Default for Chain<D> {
+    fn default() -> Chain<D> {
+        Chain::new()
+    }
+}
+
+#[async_trait]
+impl<D: Send + 'static + Sync> Middleware<D> for Chain<D> {
+    async fn invoke(&self, req: &mut Request<D>, mut res: Response<D>) -> MiddlewareResult<D> {
+        for step in self.steps.iter() {
+            match step.invoke(req, res).await? {
+                Halt(res) => return Ok(Halt(res)),
+                Continue(fresh) => res = fresh,
+            }
+        }
+        Ok(Continue(res))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{when, HttpRouter, MiddlewareResult, Nickel, Request, Response};
+    use crate::test_support::{serve, tag};
+    use hyper::StatusCode;
+
+    fn authorized(req: &mut Request, res: Response) -> MiddlewareResult {
+        if req.origin.headers().contains_key("authorization") {
+            res.next_middleware()
+        } else {
+            res.error(StatusCode::UNAUTHORIZED, "Login required")
+        }
+    }
+
+    #[tokio::test]
+    async fn chains_run_middleware_per_route() {
+        let mut server = Nickel::new();
+        server.get("/admin", chain![authorized, tag, middleware!("admin")]);
+        server.get("/open", middleware!("open"));
+        let mut group = Nickel::router();
+        group.get("/group/a", middleware!("a"));
+        server.utilize(when(|req: &Request| req.path_without_query().starts_with("/group"),
+                            chain![authorized, group]));
+        let server = serve(server).await;
+
+        for path in &["/admin", "/group/a"] {
+            assert_eq!(server.get(path).await.0, StatusCode::UNAUTHORIZED);
+
+            let req = hyper::Request::get(server.url(path))
+                .header("authorization", "yes")
+                .body(hyper::Body::empty())
+                .unwrap();
+            let res = server.send(req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers().get("x-tagged").is_some(), *path == "/admin");
+        }
+        assert_eq!(server.get("/open").await, (StatusCode::OK, "open".to_string()));
+        // Requests outside the group never reach its chain
+        assert_eq!(server.get("/other").await.0, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,251 @@
+//! Combinators that scope a middleware to some of the requests, or combine
+//! two of them. They take anything a route can be registered with,
+//! including async functions.
+use async_trait::async_trait;
+use crate::middleware::{Continue, IntoMiddleware, Middleware, MiddlewareResult};
+use crate::request::Request;
+use crate::response::Response;
+use hyper::Method;
+use std::marker::PhantomData;
+
+/// Run `middleware` only for requests matching `predicate`. Other requests
+/// are passed on to the next middleware.
+///
+/// # Examples
+/// ```rust
+/// use nickel::{Nickel, Request, StaticFilesHandler, when};
+///
+/// let mut server = Nickel::new();
+/// server.utilize(when(|req: &Request| req.origin.headers().contains_key("x-debug"),
+///                     StaticFilesHandler::new("debug/")));
+/// ```
+pub fn when<D, P, T, K>(predicate: P, middleware: T) -> When<P, T::Middleware, D>
+where D: Send + 'static + Sync,
+      T: IntoMiddleware<D, K> {
+    When { predicate, middleware: middleware.into_middleware(), _data: PhantomData }
+}
+
+/// Run `middleware` for every request except those under the path
+/// `prefix`. A prefix matches whole path segments, so `/api` skips `/api`
+/// and `/api/users` but not `/apiary`.
+///
+/// # Examples
+/// ```rust
+/// use nickel::{Nickel, Request, Response, MiddlewareResult, unless_path};
+/// use nickel::status::StatusCode;
+///
+/// fn require_login(req: &mut Request, res: Response) -> MiddlewareResult {
+///     if req.origin.headers().contains_key("authorization") {
+///         res.next_middleware()
+///     } else {
+///         res.error(StatusCode::UNAUTHORIZED, "Login required")
+///     }
+/// }
+///
+/// let mut server = Nickel::new();
+/// server.utilize(unless_path("/public", require_login));
+/// ```
+pub fn unless_path<D, S, T, K>(prefix: S, middleware: T) -> UnlessPath<T::Middleware, D>
+where D: Send + 'static + Sync,
+      S: Into<String>,
+      T: IntoMiddleware<D, K> {
+    let mut prefix = prefix.into();
+    if prefix.len() > 1 && prefix.ends_with('/') {
+        prefix.pop();
+    }
+    UnlessPath { prefix, middleware: middleware.into_middleware(), _data: PhantomData }
+}
+
+/// Run `middleware` only for requests with one of the given methods.
+///
+/// # Examples
+/// ```rust
+/// use nickel::{Nickel, StaticFilesHandler, only_methods};
+/// use nickel::hyper::Method;
+///
+/// let mut server = Nickel::new();
+/// server.utilize(only_methods([Method::GET, Method::HEAD],
+///                             StaticFilesHandler::new("public/")));
+/// ```
+pub fn only_methods<D, I, T, K>(methods: I, middleware: T) -> OnlyMethods<T::Middleware, D>
+where D: Send + 'static + Sync,
+      I: IntoIterator<Item = Method>,
+      T: IntoMiddleware<D, K> {
+    OnlyMethods {
+        methods: methods.into_iter().collect(),
+        middleware: middleware.into_middleware(),
+        _data: PhantomData,
+    }
+}
+
+/// Run `first`, and if it passes the request on, `second`. Mostly useful
+/// within the other combinators, or to register a pair of middleware as a
+/// single route handler.
+///
+/// # Examples
+/// ```rust
+/// use nickel::{Nickel, HttpRouter, StaticFilesHandler, either};
+///
+/// let mut server = Nickel::new();
+/// server.get("/assets/*", either(StaticFilesHandler::new("assets/"),
+///                                StaticFilesHandler::new("vendor/assets/")));
+/// ```
+pub fn either<D, A, B, KA, KB>(first: A, second: B) -> Either<A::Middleware, B::Middleware, D>
+where D: Send + 'static + Sync,
+      A: IntoMiddleware<D, KA>,
+      B: IntoMiddleware<D, KB> {
+    Either {
+        first: first.into_middleware(),
+        second: second.into_middleware(),
+        _data: PhantomData,
+    }
+}
+
+/// Created by `when`.
+pub struct When<P, M, D = ()> {
+    predicate: P,
+    middleware: M,
+    _data: PhantomData<fn() -> D>,
+}
+
+#[async_trait]
+impl<D, P, M> Middleware<D> for When<P, M, D>
+where D: Send + 'static + Sync,
+      P: Fn(&Request<D>) -> bool + Send + Sync + 'static,
+      M: Middleware<D> {
+    async fn invoke(&self, req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        if (self.predicate)(req) {
+            self.middleware.invoke(req, res).await
+        } else {
+            Ok(Continue(res))
+        }
+    }
+}
+
+/// Created by `unless_path`.
+pub struct UnlessPath<M, D = ()> {
+    prefix: String,
+    middleware: M,
+    _data: PhantomData<fn() -> D>,
+}
+
+impl<M, D> UnlessPath<M, D> {
+    fn skips(&self, path: &str) -> bool {
+        match path.strip_prefix(&self.prefix[..]) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/') || self.prefix.ends_with('/'),
+            None => false,
+        }
+    }
+}
+
+#[async_trait]
+impl<D, M> Middleware<D> for UnlessPath<M, D>
+where D: Send + 'static + Sync,
+      M: Middleware<D> {
+    async fn invoke(&self, req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        if self.skips(req.path_without_query()) {
+            Ok(Continue(res))
+        } else {
+            self.middleware.invoke(req, res).await
+        }
+    }
+}
+
+/// Created by `only_methods`.
+pub struct OnlyMethods<M, D = ()> {
+    methods: Vec<Method>,
+    middleware: M,
+    _data: PhantomData<fn() -> D>,
+}
+
+#[async_trait]
+impl<D, M> Middleware<D> for OnlyMethods<M, D>
+where D: Send + 'static + Sync,
+      M: Middleware<D> {
+    async fn invoke(&self, req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        if self.methods.contains(req.origin.method()) {
+            self.middleware.invoke(req, res).await
+        } else {
+            Ok(Continue(res))
+        }
+    }
+}
+
+/// Created by `either`.
+pub struct Either<A, B, D = ()> {
+    first: A,
+    second: B,
+    _data: PhantomData<fn() -> D>,
+}
+
+#[async_trait]
+impl<D, A, B> Middleware<D> for Either<A, B, D>
+where D: Send + 'static + Sync,
+      A: Middleware<D>,
+      B: Middleware<D> {
+    async fn invoke(&self, req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        match self.first.invoke(req, res).await? {
+            Continue(res) => self.second.invoke(req, res).await,
+            halted => Ok(halted),
+        }
+    }
+}
+
+#[test]
+fn unless_path_matches_whole_segments() {
+    fn pass(_: &mut Request, res: Response) -> MiddlewareResult {
+        res.next_middleware()
+    }
+
+    let skip = unless_path("/api", pass);
+    assert!(skip.skips("/api"));
+    assert!(skip.skips("/api/users"));
+    assert!(!skip.skips("/apiary"));
+    assert!(!skip.skips("/"));
+
+    let skip = unless_path("/api/", pass);
+    assert!(skip.skips("/api"));
+    assert!(skip.skips("/api/users"));
+
+    let skip = unless_path("/", pass);
+    assert!(skip.skips("/"));
+    assert!(skip.skips("/anything"));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{either, only_methods, unless_path, when};
+    use crate::{HttpRouter, MiddlewareResult, Nickel, Request, Response};
+    use crate::test_support::{serve, tag};
+    use hyper::{Method, StatusCode};
+
+    #[tokio::test]
+    async fn combinators_scope_middleware() {
+        let mut server = Nickel::new();
+        server.utilize(unless_path("/public", tag));
+        server.utilize(when(|req: &Request| req.origin.headers().contains_key("x-teapot"),
+                            middleware!((StatusCode::IM_A_TEAPOT, "teapot"))));
+        server.utilize(only_methods([Method::POST], middleware!((StatusCode::OK, "posted"))));
+        fn pass(_: &mut Request, res: Response) -> MiddlewareResult {
+            res.next_middleware()
+        }
+        server.get("/either", either(pass, middleware!("second")));
+        server.get("/**", middleware!("fallthrough"));
+        let server = serve(server).await;
+
+        let res = server.fetch("/private").await;
+        assert_eq!(res.headers()["x-tagged"], "yes");
+        let res = server.fetch("/public/file").await;
+        assert!(res.headers().get("x-tagged").is_none());
+
+        let req = hyper::Request::get(server.url("/"))
+            .header("x-teapot", "1")
+            .body(hyper::Body::empty())
+            .unwrap();
+        assert_eq!(server.send(req).await.status(), StatusCode::IM_A_TEAPOT);
+
+        assert_eq!(server.post("/", "").await, (StatusCode::OK, "posted".to_string()));
+        assert_eq!(server.get("/").await, (StatusCode::OK, "fallthrough".to_string()));
+        assert_eq!(server.get("/either").await, (StatusCode::OK, "second".to_string()));
+    }
+}
@@ -1,3 +1,4 @@
+use async_trait::async_trait;
 use hyper::StatusCode;
 use crate::request::Request;
 use crate::middleware::{ErrorHandler, Action, Halt};
@@ -6,12 +7,16 @@ use crate::nickel_error::NickelError;
 #[derive(Clone, Copy)]
 pub struct DefaultErrorHandler;
 
+#[async_trait]
 impl<D: Send + 'static + Sync> ErrorHandler<D> for DefaultErrorHandler {
-    fn handle_error(&self, err: &mut NickelError<D>, _req: &mut Request<D>) -> Action {
+    async fn handle_error(&self, err: &mut NickelError<D>, _req: &mut Request<D>) -> Action {
         if let Some(ref mut res) = err.stream {
             let msg : &[u8] = match res.status() {
                 StatusCode::NOT_FOUND => b"Not Found",
                 StatusCode::BAD_REQUEST => b"Bad Request",
+                StatusCode::PAYLOAD_TOO_LARGE => b"Payload Too Large",
+                StatusCode::UNSUPPORTED_MEDIA_TYPE => b"Unsupported Media Type",
+                StatusCode::SERVICE_UNAVAILABLE => b"Service Unavailable",
                 _ => b"Internal Server Error"
             };
 
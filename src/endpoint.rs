@@ -0,0 +1,59 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+/// One end of a connection, either a TCP socket or a Unix domain socket.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    /// A TCP socket address.
+    Tcp(SocketAddr),
+    /// A Unix domain socket. Client sockets are usually unnamed, in which
+    /// case there is no path.
+    Unix(Option<PathBuf>),
+}
+
+impl Endpoint {
+    /// The TCP socket address, if this is a TCP endpoint.
+    pub fn tcp(&self) -> Option<SocketAddr> {
+        match *self {
+            Endpoint::Tcp(addr) => Some(addr),
+            Endpoint::Unix(_) => None,
+        }
+    }
+
+    /// The filesystem path of the socket, if this is a named Unix endpoint.
+    pub fn unix_path(&self) -> Option<&Path> {
+        match *self {
+            Endpoint::Tcp(_) => None,
+            Endpoint::Unix(ref path) => path.as_deref(),
+        }
+    }
+}
+
+impl From<SocketAddr> for Endpoint {
+    fn from(addr: SocketAddr) -> Endpoint {
+        Endpoint::Tcp(addr)
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Endpoint::Tcp(ref addr) => addr.fmt(f),
+            Endpoint::Unix(Some(ref path)) => write!(f, "unix:{}", path.display()),
+            Endpoint::Unix(None) => f.write_str("unix:(unnamed)"),
+        }
+    }
+}
+
+#[test]
+fn displays_endpoints() {
+    let tcp = Endpoint::from("127.0.0.1:6767".parse::<SocketAddr>().unwrap());
+    assert_eq!(tcp.to_string(), "127.0.0.1:6767");
+    assert_eq!(tcp.tcp().unwrap().port(), 6767);
+
+    let unix = Endpoint::Unix(Some(PathBuf::from("/tmp/nickel.sock")));
+    assert_eq!(unix.to_string(), "unix:/tmp/nickel.sock");
+    assert_eq!(unix.unix_path(), Some(Path::new("/tmp/nickel.sock")));
+    assert_eq!(unix.tcp(), None);
+}
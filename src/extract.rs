@@ -0,0 +1,306 @@
+//! Typed extractors, for handlers written as async functions that take the
+//! parts of the request they need as arguments.
+//!
+//! Any async function whose arguments all implement `FromRequest`, and
+//! whose output implements `Responder`, can be registered as a handler.
+//! If an argument can't be extracted the handler isn't called, and the
+//! request fails with a `NickelError` carrying the extractor's status,
+//! usually `400 Bad Request`.
+//!
+//! # Examples
+//! ```rust
+//! #[macro_use] extern crate serde_derive;
+//! use nickel::{Nickel, HttpRouter};
+//! use nickel::extract::{Json, Path, Query};
+//!
+//! #[derive(Deserialize)]
+//! struct UserId { id: u32 }
+//!
+//! #[derive(Deserialize)]
+//! struct Paging { page: Option<u32> }
+//!
+//! #[derive(Deserialize)]
+//! struct Rename { name: String }
+//!
+//! async fn show(Path(user): Path<UserId>, Query(paging): Query<Paging>) -> String {
+//!     format!("user {} page {}", user.id, paging.page.unwrap_or(1))
+//! }
+//!
+//! async fn rename(Path(user): Path<UserId>, Json(body): Json<Rename>) -> String {
+//!     format!("user {} is now {}", user.id, body.name)
+//! }
+//!
+//! fn main() {
+//!     let mut server = Nickel::new();
+//!     server.get("/users/:id", show);
+//!     server.put("/users/:id", rename);
+//! }
+//! ```
+use async_trait::async_trait;
+use crate::middleware::{IntoMiddleware, Middleware, MiddlewareResult};
+use crate::nickel_error::NickelError;
+use crate::request::Request;
+use crate::responder::Responder;
+use crate::response::Response;
+use crate::urlencoded;
+use hyper::header::HeaderMap;
+use hyper::StatusCode;
+use serde::de::DeserializeOwned;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// Something that can be taken from a request, to be passed to a handler
+/// as an argument.
+///
+/// Failing with a status and message fails the request with a
+/// `NickelError`, which is passed on to the error handlers.
+///
+/// # Examples
+/// ```rust
+/// use async_trait::async_trait;
+/// use nickel::{Nickel, HttpRouter, Request};
+/// use nickel::extract::FromRequest;
+/// use nickel::status::StatusCode;
+///
+/// struct ApiKey(String);
+///
+/// #[async_trait]
+/// impl<D: Send + 'static + Sync> FromRequest<D> for ApiKey {
+///     async fn from_request(req: &mut Request<D>) -> Result<ApiKey, (StatusCode, String)> {
+///         req.origin.headers().get("x-api-key")
+///             .and_then(|key| key.to_str().ok())
+///             .map(|key| ApiKey(key.to_string()))
+///             .ok_or((StatusCode::UNAUTHORIZED, "missing API key".to_string()))
+///     }
+/// }
+///
+/// async fn whoami(ApiKey(key): ApiKey) -> String {
+///     format!("Your key is {}", key)
+/// }
+///
+/// let mut server = Nickel::new();
+/// server.get("/whoami", whoami);
+/// ```
+#[async_trait]
+pub trait FromRequest<D: Send + 'static + Sync>: Sized + Send + 'static {
+    async fn from_request(req: &mut Request<D>) -> Result<Self, (StatusCode, String)>;
+}
+
+/// The route parameters, deserialized into `T`, usually a struct with a
+/// field per parameter.
+pub struct Path<T>(pub T);
+
+#[async_trait]
+impl<D, T> FromRequest<D> for Path<T>
+where D: Send + 'static + Sync,
+      T: DeserializeOwned + Send + 'static {
+    async fn from_request(req: &mut Request<D>) -> Result<Path<T>, (StatusCode, String)> {
+        let params = urlencoded::from_pairs(req.route_result.iter().flat_map(|route| route.params()));
+        urlencoded::from_params(&params)
+            .map(Path)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid route parameters: {}", e)))
+    }
+}
+
+/// The query string, deserialized into `T` as by `Request::query_as`.
+pub struct Query<T>(pub T);
+
+#[async_trait]
+impl<D, T> FromRequest<D> for Query<T>
+where D: Send + 'static + Sync,
+      T: DeserializeOwned + Send + 'static {
+    async fn from_request(req: &mut Request<D>) -> Result<Query<T>, (StatusCode, String)> {
+        req.query_as().map(Query)
+    }
+}
+
+/// The body, deserialized from JSON into `T`.
+pub struct Json<T>(pub T);
+
+#[async_trait]
+impl<D, T> FromRequest<D> for Json<T>
+where D: Send + 'static + Sync,
+      T: DeserializeOwned + Send + 'static {
+    async fn from_request(req: &mut Request<D>) -> Result<Json<T>, (StatusCode, String)> {
+        req.json_as().await.map(Json)
+    }
+}
+
+/// The body, deserialized from `application/x-www-form-urlencoded` into
+/// `T` as by `Request::form_as`.
+pub struct Form<T>(pub T);
+
+#[async_trait]
+impl<D, T> FromRequest<D> for Form<T>
+where D: Send + 'static + Sync,
+      T: DeserializeOwned + Send + 'static {
+    async fn from_request(req: &mut Request<D>) -> Result<Form<T>, (StatusCode, String)> {
+        req.form_as().await.map(Form)
+    }
+}
+
+/// A copy of the request headers.
+pub struct Headers(pub HeaderMap);
+
+#[async_trait]
+impl<D: Send + 'static + Sync> FromRequest<D> for Headers {
+    async fn from_request(req: &mut Request<D>) -> Result<Headers, (StatusCode, String)> {
+        Ok(Headers(req.origin.headers().clone()))
+    }
+}
+
+/// The data the server was created with, see `Nickel::with_data`.
+pub struct ServerData<D>(pub Arc<D>);
+
+#[async_trait]
+impl<D: Send + 'static + Sync> FromRequest<D> for ServerData<D> {
+    async fn from_request(req: &mut Request<D>) -> Result<ServerData<D>, (StatusCode, String)> {
+        Ok(ServerData(req.server_data()))
+    }
+}
+
+/// `None` where `T` can't be extracted, instead of failing the request.
+#[async_trait]
+impl<D, T> FromRequest<D> for Option<T>
+where D: Send + 'static + Sync,
+      T: FromRequest<D> {
+    async fn from_request(req: &mut Request<D>) -> Result<Option<T>, (StatusCode, String)> {
+        Ok(T::from_request(req).await.ok())
+    }
+}
+
+/// Adapts an async function taking extractors into a `Middleware`. Created
+/// by `IntoMiddleware`.
+pub struct ExtractorHandler<F, Args> {
+    handler: F,
+    _args: PhantomData<fn() -> Args>,
+}
+
+/// Marker for `IntoMiddleware` on async functions taking extractors.
+pub struct WithExtractors<Args>(PhantomData<fn() -> Args>);
+
+macro_rules! extractor_handler {
+    ($($arg:ident),*) => {
+        #[async_trait]
+        #[allow(non_snake_case, unused_variables, unused_mut)]
+        impl<D, F, Fut, R, $($arg,)*> Middleware<D> for ExtractorHandler<F, ($($arg,)*)>
+        where D: Send + 'static + Sync,
+              F: Fn($($arg),*) -> Fut + Send + Sync + 'static,
+              Fut: Future<Output = R> + Send,
+              R: Responder<D>,
+              $($arg: FromRequest<D>,)* {
+            async fn invoke(&self, req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+                $(
+                    let $arg = match $arg::from_request(req).await {
+                        Ok(value) => value,
+                        Err(rejection) => return Err(NickelError::from((res, rejection))),
+                    };
+                )*
+                (self.handler)($($arg),*).await.respond(res)
+            }
+        }
+
+        impl<D, F, Fut, R, $($arg,)*> IntoMiddleware<D, WithExtractors<($($arg,)*)>> for F
+        where D: Send + 'static + Sync,
+              F: Fn($($arg),*) -> Fut + Send + Sync + 'static,
+              Fut: Future<Output = R> + Send,
+              R: Responder<D>,
+              $($arg: FromRequest<D>,)* {
+            type Middleware = ExtractorHandler<F, ($($arg,)*)>;
+
+            fn into_middleware(self) -> Self::Middleware {
+                ExtractorHandler { handler: self, _args: PhantomData }
+            }
+        }
+    };
+}
+
+extractor_handler!();
+extractor_handler!(A);
+extractor_handler!(A, B);
+extractor_handler!(A, B, C);
+extractor_handler!(A, B, C, E);
+extractor_handler!(A, B, C, E, G);
+extractor_handler!(A, B, C, E, G, H);
+
+#[cfg(test)]
+mod tests {
+    use super::{Form, Headers, Json, Path, Query, ServerData};
+    use crate::{HttpRouter, Nickel};
+    use crate::test_support::{read, serve};
+    use hyper::StatusCode;
+    use serde_derive::Deserialize;
+
+    #[derive(Deserialize)]
+    struct UserId { id: u32 }
+
+    #[derive(Deserialize)]
+    struct Paging { page: Option<u32> }
+
+    #[derive(Deserialize)]
+    struct Rename { name: String }
+
+    async fn show(Path(user): Path<UserId>, Query(paging): Query<Paging>) -> String {
+        format!("user {} page {}", user.id, paging.page.unwrap_or(1))
+    }
+
+    async fn rename(Path(user): Path<UserId>, Json(body): Json<Rename>) -> String {
+        format!("user {} is now {}", user.id, body.name)
+    }
+
+    async fn form(Form(body): Form<Rename>, Headers(headers): Headers) -> String {
+        format!("{} via {}", body.name, headers["x-client"].to_str().unwrap())
+    }
+
+    async fn data(ServerData(data): ServerData<&'static str>, json: Option<Json<Rename>>) -> String {
+        format!("{} {}", data, json.map(|Json(body)| body.name).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn handlers_take_extractors() {
+        let mut server = Nickel::new();
+        server.get("/users/:id", show);
+        server.put("/users/:id", rename);
+        server.post("/form", form);
+        let server = serve(server).await;
+
+        assert_eq!(server.get("/users/7?page=2").await, (StatusCode::OK, "user 7 page 2".to_string()));
+        assert_eq!(server.get("/users/7").await, (StatusCode::OK, "user 7 page 1".to_string()));
+        assert_eq!(server.get("/users/seven").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(server.get("/users/7?page=last").await.0, StatusCode::BAD_REQUEST);
+
+        let client = &server;
+        let send = |method: &str, path: &str, content_type: &str, body: &'static str| {
+            let req = hyper::Request::builder()
+                .method(method)
+                .uri(server.url(path))
+                .header("content-type", content_type)
+                .header("x-client", "test")
+                .body(hyper::Body::from(body))
+                .unwrap();
+            async move { read(client.send(req).await).await }
+        };
+        assert_eq!(send("PUT", "/users/7", "application/json", r#"{"name":"Bob"}"#).await,
+                   (StatusCode::OK, "user 7 is now Bob".to_string()));
+        assert_eq!(send("PUT", "/users/7", "application/json", "{}").await.0, StatusCode::BAD_REQUEST);
+
+        let form = "application/x-www-form-urlencoded";
+        assert_eq!(send("POST", "/form", form, "name=Al+Bo").await, (StatusCode::OK, "Al Bo via test".to_string()));
+        assert_eq!(send("POST", "/form", "text/plain", "name=Al").await,
+                   (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Unsupported Media Type".to_string()));
+        let latin1 = "application/x-www-form-urlencoded; charset=ISO-8859-1";
+        assert_eq!(send("POST", "/form", latin1, "name=Ren%E9e").await.1, "Renée via test");
+        // Forms are never sent as UTF-16, browsers use UTF-8 instead
+        let utf16 = "application/x-www-form-urlencoded; charset=utf-16";
+        assert_eq!(send("POST", "/form", utf16, "name=Ren%C3%A9e").await.1, "Renée via test");
+        let unknown = "application/x-www-form-urlencoded; charset=klingon";
+        assert_eq!(send("POST", "/form", unknown, "name=Al").await.0, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let mut server = Nickel::with_data("shared");
+        server.post("/data", data);
+        let server = serve(server).await;
+        assert_eq!(server.post("/data", r#"{"name":"json"}"#).await, (StatusCode::OK, "shared json".to_string()));
+        assert_eq!(server.post("/data", "not json").await, (StatusCode::OK, "shared ".to_string()));
+    }
+}
@@ -9,6 +9,8 @@ pub use crate::nickel::{Nickel, Options};
 pub use crate::request::Request;
 pub use crate::response::Response;
 pub use crate::middleware::{Action, Continue, Halt, Middleware, ErrorHandler, MiddlewareResult};
+pub use crate::middleware::{AsyncHandler, AsyncMiddleware, IntoMiddleware};
+pub use crate::middleware::{AroundHandler, AroundMiddleware, Next};
 pub use crate::static_files_handler::StaticFilesHandler;
 pub use crate::mount::{Mount, Mountable};
 pub use crate::favicon_handler::FaviconHandler;
@@ -20,13 +22,34 @@ pub use crate::router::{Router, Route, RouteResult, HttpRouter};
 pub use crate::nickel_error::NickelError;
 pub use crate::mimes::MediaType;
 pub use crate::responder::Responder;
-pub use crate::server::Server;
+pub use crate::server::{Server, ListeningServer};
+#[cfg(feature = "tower")]
+pub use crate::service::NickelService;
+pub use crate::endpoint::Endpoint;
+pub use crate::body_limit::BodyLimit;
+pub use crate::chain::Chain;
+pub use crate::combinators::{when, unless_path, only_methods, either, When, UnlessPath, OnlyMethods, Either};
+pub use crate::limits::{OverloadPolicy, ServerStats};
+#[cfg(unix)]
+pub use crate::activation::listen_fds;
+#[cfg(feature = "ssl")]
+pub use crate::tls::TlsConfig;
 pub use crate::template_cache::{ReloadPolicy, TemplateCache};
 
 #[macro_use] pub mod macros;
 
 pub mod router;
 mod server;
+mod service;
+mod endpoint;
+mod body_limit;
+mod chain;
+mod combinators;
+mod limits;
+mod activation;
+mod catch_panic;
+#[cfg(feature = "ssl")]
+mod tls;
 mod nickel;
 mod request;
 mod response;
@@ -46,7 +69,11 @@ mod urlencoded;
 mod nickel_error;
 mod default_error_handler;
 pub mod extensions;
+pub mod extract;
+pub mod multipart;
 pub mod template_cache;
+#[cfg(test)]
+mod test_support;
 
 pub mod status {
     pub use hyper::StatusCode;
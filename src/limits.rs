@@ -0,0 +1,275 @@
+use hyper::{Body, Response as HyperResponse, StatusCode};
+use hyper::header::{self, HeaderValue};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// What the server does with connections or requests that arrive while it
+/// is at `Options::max_connections` or `Options::max_in_flight_requests`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverloadPolicy {
+    /// Wait for capacity. Excess connections are left in the listen backlog
+    /// and excess requests wait before reaching the middleware.
+    Queue,
+    /// Answer immediately with `503 Service Unavailable`, asking the client
+    /// to try again after the given duration with a `Retry-After` header.
+    /// Rejected connections are closed after the response.
+    Reject { retry_after: Duration },
+}
+
+/// Live counters for a server, for example to report saturation from a
+/// health check.
+///
+/// # Examples
+/// ```rust
+/// # #[macro_use] extern crate nickel;
+/// # fn main() {
+/// use nickel::{Nickel, HttpRouter};
+///
+/// let mut server = Nickel::new();
+/// let stats = server.stats();
+/// server.get("/health", middleware! {
+///     format!("connections={} in_flight={}",
+///             stats.open_connections(),
+///             stats.in_flight_requests())
+/// });
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct ServerStats {
+    counters: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    open_connections: AtomicUsize,
+    in_flight_requests: AtomicUsize,
+    rejected_connections: AtomicU64,
+    rejected_requests: AtomicU64,
+}
+
+impl ServerStats {
+    /// The number of client connections currently open.
+    pub fn open_connections(&self) -> usize {
+        self.counters.open_connections.load(Ordering::Relaxed)
+    }
+
+    /// The number of requests currently being handled by the middleware.
+    pub fn in_flight_requests(&self) -> usize {
+        self.counters.in_flight_requests.load(Ordering::Relaxed)
+    }
+
+    /// The total number of connections turned away because the server was
+    /// at `max_connections`.
+    pub fn rejected_connections(&self) -> u64 {
+        self.counters.rejected_connections.load(Ordering::Relaxed)
+    }
+
+    /// The total number of requests turned away because the server was at
+    /// `max_in_flight_requests`.
+    pub fn rejected_requests(&self) -> u64 {
+        self.counters.rejected_requests.load(Ordering::Relaxed)
+    }
+}
+
+/// Connection and request limits shared by everything serving one listener.
+pub(crate) struct Limits {
+    stats: ServerStats,
+    policy: OverloadPolicy,
+    connections: Option<Arc<Semaphore>>,
+    requests: Option<Arc<Semaphore>>,
+}
+
+impl Limits {
+    pub(crate) fn new(stats: ServerStats,
+                      policy: OverloadPolicy,
+                      max_connections: Option<usize>,
+                      max_requests: Option<usize>) -> Limits {
+        Limits {
+            stats,
+            policy,
+            connections: max_connections.map(|max| Arc::new(Semaphore::new(max))),
+            requests: max_requests.map(|max| Arc::new(Semaphore::new(max))),
+        }
+    }
+
+    /// The connection semaphore, if connections should be queued for it.
+    pub(crate) fn queued_connections(&self) -> Option<Arc<Semaphore>> {
+        match self.policy {
+            OverloadPolicy::Queue => self.connections.clone(),
+            OverloadPolicy::Reject { .. } => None,
+        }
+    }
+
+    /// Track a newly accepted connection. `permit` is the connection permit
+    /// when queueing, otherwise one is taken here if there is room. Returns
+    /// `None` for a connection that should be rejected.
+    pub(crate) fn admit_connection(&self, permit: Option<OwnedSemaphorePermit>) -> Option<ConnectionGuard> {
+        let permit = match (permit, &self.connections) {
+            (Some(permit), _) => Some(permit),
+            (None, Some(sem)) => match sem.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    self.stats.counters.rejected_connections.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+            },
+            (None, None) => None,
+        };
+        self.stats.counters.open_connections.fetch_add(1, Ordering::Relaxed);
+        Some(ConnectionGuard { stats: self.stats.clone(), _permit: permit })
+    }
+
+    /// Wait for, or check for, room to handle another request. Returns
+    /// `None` for a request that should be rejected.
+    pub(crate) async fn admit_request(&self) -> Option<RequestGuard> {
+        let permit = match (self.policy, &self.requests) {
+            (_, None) => None,
+            (OverloadPolicy::Queue, Some(sem)) => sem.clone().acquire_owned().await.ok(),
+            (OverloadPolicy::Reject { .. }, Some(sem)) => match sem.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    self.stats.counters.rejected_requests.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+            },
+        };
+        self.stats.counters.in_flight_requests.fetch_add(1, Ordering::Relaxed);
+        Some(RequestGuard { stats: self.stats.clone(), _permit: permit })
+    }
+
+    /// The response sent in place of handling a rejected request.
+    pub(crate) fn overloaded(&self, close: bool) -> HyperResponse<Body> {
+        let mut res = HyperResponse::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE);
+        if let OverloadPolicy::Reject { retry_after } = self.policy {
+            // Retry-After is in whole seconds, round up so clients don't
+            // come back too early
+            let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            res = res.header(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        if close {
+            res = res.header(header::CONNECTION, HeaderValue::from_static("close"));
+        }
+        res.body(Body::from("Service Unavailable")).unwrap()
+    }
+}
+
+/// Held for as long as a connection is open.
+pub(crate) struct ConnectionGuard {
+    stats: ServerStats,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.stats.counters.open_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Held for as long as a request is being handled.
+pub(crate) struct RequestGuard {
+    stats: ServerStats,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.stats.counters.in_flight_requests.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{HttpRouter, Nickel, Options, OverloadPolicy};
+    use crate::test_support::{closed_within, get_raw, serve, slow};
+    use hyper::{Client, StatusCode};
+    use std::time::Duration;
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn rejects_requests_over_in_flight_limit() {
+        let mut server = Nickel::new();
+        server.options = Options::default()
+            .max_in_flight_requests(Some(1))
+            .overload_policy(OverloadPolicy::Reject { retry_after: Duration::from_millis(1500) });
+        let stats = server.stats();
+        server.get("/slow", slow);
+        let server = serve(server).await;
+        let url: hyper::Uri = server.url("/slow").parse().unwrap();
+
+        let first = tokio::spawn(Client::new().get(url.clone()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(stats.in_flight_requests(), 1);
+
+        let res = Client::new().get(url).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()[hyper::header::RETRY_AFTER], "2");
+        assert_eq!(stats.rejected_requests(), 1);
+
+        assert_eq!(first.await.unwrap().unwrap().status(), StatusCode::OK);
+        assert_eq!(stats.in_flight_requests(), 0);
+    }
+
+    #[tokio::test]
+    async fn queues_requests_over_in_flight_limit() {
+        let mut server = Nickel::new();
+        server.options = Options::default().max_in_flight_requests(Some(1));
+        server.get("/slow", slow);
+        let server = serve(server).await;
+        let url: hyper::Uri = server.url("/slow").parse().unwrap();
+
+        let start = std::time::Instant::now();
+        let (a, b) = tokio::join!(Client::new().get(url.clone()), Client::new().get(url));
+        assert_eq!(a.unwrap().status(), StatusCode::OK);
+        assert_eq!(b.unwrap().status(), StatusCode::OK);
+        assert!(start.elapsed() >= Duration::from_millis(600));
+    }
+
+    #[tokio::test]
+    async fn rejects_connections_over_limit() {
+        let mut server = Nickel::new();
+        server.options = Options::default()
+            .max_connections(Some(1))
+            .overload_policy(OverloadPolicy::Reject { retry_after: Duration::from_secs(1) });
+        let stats = server.stats();
+        server.get("/", middleware!("hi"));
+        let server = serve(server).await;
+
+        let mut first = TcpStream::connect(server.addr()).await.unwrap();
+        assert!(get_raw(&mut first, "/", true).await.ends_with("hi"));
+        assert_eq!(stats.open_connections(), 1);
+
+        let mut second = TcpStream::connect(server.addr()).await.unwrap();
+        let res = get_raw(&mut second, "/", true).await;
+        assert!(res.starts_with("HTTP/1.1 503"), "{}", res);
+        assert!(res.contains("retry-after: 1"));
+        assert!(closed_within(&mut second, Duration::from_secs(1)).await);
+        assert_eq!(stats.rejected_connections(), 1);
+
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server.listening().stats().open_connections(), 0);
+    }
+
+    #[tokio::test]
+    async fn queues_connections_over_limit() {
+        let mut server = Nickel::new();
+        server.options = Options::default().max_connections(Some(1));
+        server.get("/", middleware!("hi"));
+        let server = serve(server).await;
+
+        let mut first = TcpStream::connect(server.addr()).await.unwrap();
+        assert!(get_raw(&mut first, "/", true).await.ends_with("hi"));
+
+        // waits in the backlog until the first connection goes away
+        let mut second = TcpStream::connect(server.addr()).await.unwrap();
+        let pending = tokio::spawn(async move { get_raw(&mut second, "/", false).await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!pending.is_finished());
+
+        drop(first);
+        assert!(pending.await.unwrap().ends_with("hi"));
+    }
+}
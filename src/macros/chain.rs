@@ -0,0 +1,26 @@
+/// Builds a `Chain` from a list of middleware, run in the order given.
+///
+/// Each item can be anything accepted by `HttpRouter::get` and friends,
+/// including async functions.
+///
+/// # Examples
+/// ```rust
+/// #[macro_use] extern crate nickel;
+/// use nickel::{Nickel, HttpRouter, Request, Response, MiddlewareResult, StaticFilesHandler};
+///
+/// fn log(req: &mut Request, res: Response) -> MiddlewareResult {
+///     println!("docs: {}", req.path_without_query());
+///     res.next_middleware()
+/// }
+///
+/// fn main() {
+///     let mut server = Nickel::new();
+///     server.get("/docs/*", chain![log, StaticFilesHandler::new("docs/")]);
+/// }
+/// ```
+#[macro_export]
+macro_rules! chain {
+    ($($middleware:expr),+ $(,)?) => {
+        $crate::Chain::new()$(.link($middleware))+
+    };
+}
@@ -1,5 +1,6 @@
 #[macro_use] mod middleware;
 #[macro_use] mod router;
+#[macro_use] mod chain;
 
 #[macro_export]
 macro_rules! try_with {
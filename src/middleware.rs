@@ -1,11 +1,22 @@
 use async_trait::async_trait;
+use crate::catch_panic;
 use crate::request::Request;
 use crate::response::Response;
 use crate::nickel_error::NickelError;
-use hyper::{Body, Response as HyperResponse};
+use hyper::{Body, Response as HyperResponse, StatusCode};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use futures::FutureExt;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+use tokio::time;
 
 pub use self::Action::{Continue, Halt};
 
+pub(crate) type LastResort = Box<dyn Fn(&str) -> HyperResponse<Body> + Send + Sync>;
+
 pub type MiddlewareResult<D= ()> = Result<Action<Response<D>>,
                                           NickelError<D>>;
 
@@ -33,82 +44,661 @@ where T: Fn(&mut Request<D>, Response<D>) -> MiddlewareResult<D> + Send + Sync +
     }
 }
 
+/// An async function or closure that can be used as a `Middleware`, taking
+/// the request by mutable reference for as long as it runs.
+///
+/// This is implemented for every suitable `async fn` and async closure, and
+/// only needs to be named in bounds. The lifetime parameter is what lets the
+/// returned future borrow the request.
+pub trait AsyncHandler<'a, D: Send + 'static + Sync>: Send + Sync + 'static {
+    type Future: Future<Output = MiddlewareResult<D>> + Send + 'a;
+
+    fn call(&self, req: &'a mut Request<D>, res: Response<D>) -> Self::Future;
+}
+
+impl<'a, D, F, Fut> AsyncHandler<'a, D> for F
+where F: Fn(&'a mut Request<D>, Response<D>) -> Fut + Send + Sync + 'static,
+      Fut: Future<Output = MiddlewareResult<D>> + Send + 'a,
+      D: Send + 'static + Sync
+{
+    type Future = Fut;
+
+    fn call(&self, req: &'a mut Request<D>, res: Response<D>) -> Fut {
+        (*self)(req, res)
+    }
+}
+
+/// Adapts an `AsyncHandler` into a `Middleware`. Created by `IntoMiddleware`,
+/// which `get`, `post`, `utilize` and friends use to accept async functions
+/// directly.
+pub struct AsyncMiddleware<F>(F);
+
+#[async_trait]
+impl<D, F> Middleware<D> for AsyncMiddleware<F>
+where F: for<'a> AsyncHandler<'a, D>,
+      D: Send + 'static + Sync
+{
+    async fn invoke(&self, req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        self.0.call(req, res).await
+    }
+}
+
+/// Anything that can be registered as middleware: types implementing
+/// `Middleware` (including plain functions) as well as async functions and
+/// closures.
+///
+/// The `Kind` parameter only exists to keep the implementations apart and
+/// is always inferred.
+///
+/// # Examples
+/// ```rust
+/// use nickel::{Nickel, HttpRouter, Request, Response, MiddlewareResult};
+///
+/// async fn hello(req: &mut Request, res: Response) -> MiddlewareResult {
+///     let name = req.string_body().await.unwrap_or_default();
+///     res.send(format!("Hello {}", name))
+/// }
+///
+/// let mut server = Nickel::new();
+/// server.post("/hello", hello);
+/// server.get("/bye", async |_req: &mut Request, res: Response| res.send("Bye"));
+/// ```
+pub trait IntoMiddleware<D: Send + 'static + Sync, Kind> {
+    type Middleware: Middleware<D>;
+
+    fn into_middleware(self) -> Self::Middleware;
+}
+
+/// Marker for `IntoMiddleware` on types that already implement `Middleware`.
+pub enum IsMiddleware {}
+
+/// Marker for `IntoMiddleware` on async functions and closures.
+pub enum IsAsyncFn {}
+
+impl<D: Send + 'static + Sync, M: Middleware<D>> IntoMiddleware<D, IsMiddleware> for M {
+    type Middleware = M;
+
+    fn into_middleware(self) -> M {
+        self
+    }
+}
+
+impl<D, F> IntoMiddleware<D, IsAsyncFn> for F
+where F: for<'a> AsyncHandler<'a, D>,
+      D: Send + 'static + Sync
+{
+    type Middleware = AsyncMiddleware<F>;
+
+    fn into_middleware(self) -> AsyncMiddleware<F> {
+        AsyncMiddleware(self)
+    }
+}
+
+/// Middleware that runs around everything registered after it, with a
+/// `Next` to invoke the rest of the stack. It sees what the rest of the
+/// stack returned and may change it, or not call `Next` at all.
+///
+/// Register with `Nickel::around`. Async functions and closures taking a
+/// `Next` as their third argument implement this trait too.
+///
+/// # Examples
+/// ```rust
+/// use nickel::{Nickel, HttpRouter, Request, Response, MiddlewareResult, Next};
+/// use std::time::Instant;
+///
+/// async fn timing(req: &mut Request, res: Response, next: Next<'_>) -> MiddlewareResult {
+///     let started = Instant::now();
+///     let result = next.run(req, res).await;
+///     println!("{} took {:?}", req.path_without_query(), started.elapsed());
+///     result
+/// }
+///
+/// let mut server = Nickel::new();
+/// server.around(timing);
+/// server.get("/", async |_req: &mut Request, res: Response| res.send("Hello"));
+/// ```
+#[async_trait]
+pub trait AroundMiddleware<D: Send + 'static + Sync>: Send + 'static + Sync {
+    async fn around(&self, req: &mut Request<D>, res: Response<D>, next: Next<'_, D>) -> MiddlewareResult<D>;
+}
+
+/// An async function or closure that can be used as an `AroundMiddleware`.
+/// Like `AsyncHandler`, this only needs to be named in bounds.
+pub trait AroundHandler<'a, D: Send + 'static + Sync>: Send + Sync + 'static {
+    type Future: Future<Output = MiddlewareResult<D>> + Send + 'a;
+
+    fn call(&self, req: &'a mut Request<D>, res: Response<D>, next: Next<'a, D>) -> Self::Future;
+}
+
+impl<'a, D, F, Fut> AroundHandler<'a, D> for F
+where F: Fn(&'a mut Request<D>, Response<D>, Next<'a, D>) -> Fut + Send + Sync + 'static,
+      Fut: Future<Output = MiddlewareResult<D>> + Send + 'a,
+      D: Send + 'static + Sync
+{
+    type Future = Fut;
+
+    fn call(&self, req: &'a mut Request<D>, res: Response<D>, next: Next<'a, D>) -> Fut {
+        (*self)(req, res, next)
+    }
+}
+
+#[async_trait]
+impl<D, F> AroundMiddleware<D> for F
+where F: for<'a> AroundHandler<'a, D>,
+      D: Send + 'static + Sync
+{
+    async fn around(&self, req: &mut Request<D>, res: Response<D>, next: Next<'_, D>) -> MiddlewareResult<D> {
+        self.call(req, res, next).await
+    }
+}
+
+/// The rest of the middleware stack, as seen by an `AroundMiddleware`.
+pub struct Next<'a, D: Send + 'static + Sync = ()> {
+    layers: &'a [Layer<D>],
+    fallback: Option<&'a (dyn Middleware<D> + Send + Sync)>,
+}
+
+impl<'a, D: Send + 'static + Sync> Next<'a, D> {
+    /// Run the rest of the stack, and the fallback handler if nothing
+    /// halts. Returns `Halt` with the response of the middleware that
+    /// halted, `Continue` if none did, or the error if one failed. Error
+    /// handlers run later, on whatever the outermost middleware returns.
+    pub async fn run(self, req: &mut Request<D>, mut res: Response<D>) -> MiddlewareResult<D> {
+        for (i, layer) in self.layers.iter().enumerate() {
+            match layer {
+                Layer::Middleware(handler) => match handler.invoke(req, res).await? {
+                    Halt(res) => return Ok(Halt(res)),
+                    Continue(fresh) => res = fresh,
+                },
+                Layer::Around(handler) => {
+                    let next = Next { layers: &self.layers[i + 1..], fallback: self.fallback };
+                    return handler.around(req, res, next).await;
+                }
+            }
+        }
+        match self.fallback {
+            Some(fallback) => fallback.invoke(req, res).await,
+            None => Ok(Continue(res)),
+        }
+    }
+}
+
+enum Layer<D: Send + 'static + Sync> {
+    Middleware(Box<dyn Middleware<D> + Send + Sync>),
+    Around(Box<dyn AroundMiddleware<D> + Send + Sync>),
+}
+
+/// Handles errors returned from middleware. Error handlers are tried in
+/// reverse order of registration until one of them returns `Halt`.
+///
+/// Plain functions of the form
+/// `fn(&mut NickelError<D>, &mut Request<D>) -> Action` are error handlers
+/// too, for handlers that don't need to await anything.
+///
+/// # Examples
+/// ```rust
+/// use async_trait::async_trait;
+/// use nickel::{Action, Continue, ErrorHandler, Halt, Nickel, NickelError, Request};
+/// use nickel::status::StatusCode;
+///
+/// struct NotFoundPage;
+///
+/// #[async_trait]
+/// impl<D: Send + 'static + Sync> ErrorHandler<D> for NotFoundPage {
+///     async fn handle_error(&self, err: &mut NickelError<D>, _req: &mut Request<D>) -> Action {
+///         if let Some(ref mut res) = err.stream {
+///             if res.status() == StatusCode::NOT_FOUND {
+///                 let page = tokio::fs::read("assets/404.html").await.unwrap_or_default();
+///                 res.set_body(page);
+///                 return Halt(());
+///             }
+///         }
+///         Continue(())
+///     }
+/// }
+///
+/// let mut server = Nickel::new();
+/// server.handle_error(NotFoundPage);
+/// ```
+#[async_trait]
 pub trait ErrorHandler<D: Send + 'static + Sync>: Send + 'static + Sync {
-    fn handle_error(&self, _: &mut NickelError<D>, _: &mut Request<D>) -> Action;
+    async fn handle_error(&self, _: &mut NickelError<D>, _: &mut Request<D>) -> Action;
 }
 
-impl<D: Send + 'static + Sync> ErrorHandler<D> for fn(&mut NickelError<D>, &mut Request<D>) -> Action {
-    fn handle_error(&self, err: &mut NickelError<D>, req: &mut Request<D>) -> Action {
+#[async_trait]
+impl<T, D> ErrorHandler<D> for T
+where T: Fn(&mut NickelError<D>, &mut Request<D>) -> Action + Send + Sync + 'static,
+      D: Send + 'static + Sync
+{
+    async fn handle_error(&self, err: &mut NickelError<D>, req: &mut Request<D>) -> Action {
         (*self)(err, req)
     }
 }
 
 pub struct MiddlewareStack<D: Send + 'static + Sync = ()> {
-    handlers: Vec<Box<dyn Middleware<D> + Send + Sync>>,
-    error_handlers: Vec<Box<dyn ErrorHandler<D> + Send + Sync>>
+    handlers: Vec<Layer<D>>,
+    error_handlers: Vec<Box<dyn ErrorHandler<D> + Send + Sync>>,
+    request_timeout: Option<Duration>,
+    last_resort: Option<LastResort>,
+    catch_panics: bool,
+    fallback: Option<Box<dyn Middleware<D> + Send + Sync>>,
 }
 
 impl<D: Send + 'static + Sync> MiddlewareStack<D> {
     pub fn add_middleware<T: Middleware<D>> (&mut self, handler: T) {
-        self.handlers.push(Box::new(handler));
+        self.handlers.push(Layer::Middleware(Box::new(handler)));
+    }
+
+    pub fn add_around<T: AroundMiddleware<D>> (&mut self, handler: T) {
+        self.handlers.push(Layer::Around(Box::new(handler)));
+    }
+
+    pub fn set_fallback<T: Middleware<D>> (&mut self, handler: T) {
+        self.fallback = Some(Box::new(handler));
     }
 
     pub fn add_error_handler<T: ErrorHandler<D>> (&mut self, handler: T) {
         self.error_handlers.push(Box::new(handler));
     }
 
-    pub async fn invoke(&self, mut req: Request<D>, mut res: Response<D>) -> HyperResponse<Body> {
-        for handler in self.handlers.iter() {
-            match handler.invoke(&mut req, res).await {
-                Ok(Halt(res)) => {
-                    debug!("Halted {:?} {:?} {:?} {:?}",
+    /// Run the request through the middleware and then, on error, the error
+    /// handlers. Errs only when the connection should be dropped without a
+    /// response, as asked for by `Response::bail`.
+    pub async fn invoke(&self, mut req: Request<D>, res: Response<D>) -> Result<HyperResponse<Body>, Aborted> {
+        // The handlers own `res` while they run, keep a blank one sharing its
+        // on_send hooks around in case they don't give it back.
+        let spare = res.fresh();
+        let result = match self.request_timeout {
+            Some(timeout) => {
+                match time::timeout(timeout, self.run_handlers(&mut req, res)).await {
+                    Ok(result) => result,
+                    Err(_) => Err(NickelError::new(spare.fresh(),
+                                                   format!("Request timed out after {:?}", timeout),
+                                                   StatusCode::SERVICE_UNAVAILABLE)),
+                }
+            },
+            None => self.run_handlers(&mut req, res).await,
+        };
+
+        match result {
+            Ok(Halt(res)) => {
+                debug!("Halted {:?} {:?} {:?} {:?}",
+                       req.origin.method(),
+                       req.remote_addr(),
+                       req.origin.uri(),
+                       res.status());
+                // let _ = res.end();
+                Ok(res.finish())
+            },
+            // Neither the middleware nor the fallback halted
+            Ok(Continue(res)) => Ok(res.finish()),
+            Err(mut err) => {
+                warn!("{:?} {:?} {:?} {:?} {:?}",
+                      req.origin.method(),
+                      req.remote_addr(),
+                      req.origin.uri(),
+                      err.message,
+                      err.stream.as_ref().map(|s| s.status()));
+
+                let mut halted = false;
+                for error_handler in self.error_handlers.iter().rev() {
+                    if let Halt(()) = error_handler.handle_error(&mut err, &mut req).await {
+                        halted = true;
+                        break;
+                    }
+                }
+
+                if err.is_bail() {
+                    error!("Dropping connection: {:?} {:?} {:?} {:?}",
                            req.origin.method(),
                            req.remote_addr(),
                            req.origin.uri(),
-                           res.status());
-                    // let _ = res.end();
-                    return res.origin;
-                },
-                Ok(Continue(fresh)) => res = fresh,
-                Err(mut err) => {
-                    warn!("{:?} {:?} {:?} {:?} {:?}",
-                          req.origin.method(),
-                          req.remote_addr(),
-                          req.origin.uri(),
-                          err.message,
-                          err.stream.as_ref().map(|s| s.status()));
-
-                    for error_handler in self.error_handlers.iter().rev() {
-                        if let Halt(()) = error_handler.handle_error(&mut err, &mut req) {
-                            if let Some(res) = err.stream {
-                                return res.origin;
-                            } else {
-                                error!("Error without Response struct");
-                                // Create a new Response with an InternalServerError
-
-                                panic!("Error without Response struct"); // TODO: migration cleanup - return error
-                            }
-                        }
-                    }
+                           err.message);
+                    return Err(Aborted(err.message));
+                }
 
-                    warn!("Unhandled Error: {:?} {:?} {:?} {:?} {:?}",
-                          req.origin.method(),
-                          req.remote_addr(),
-                          req.origin.uri(),
-                          err.message,
-                          err.stream.map(|s| s.status()));
-                    panic!("Unhandled Error"); // TODO: migration cleanup - return error
+                match err.stream {
+                    Some(res) if halted => Ok(res.finish()),
+                    stream => {
+                        error!("Unhandled Error: {:?} {:?} {:?} {:?} {:?}",
+                               req.origin.method(),
+                               req.remote_addr(),
+                               req.origin.uri(),
+                               err.message,
+                               stream.as_ref().map(|s| s.status()));
+                        let last_resort = self.last_resort(&err.message);
+                        // Send it through a response so the on_send hooks
+                        // still see what gets sent
+                        let mut res = stream.unwrap_or(spare);
+                        res.origin = last_resort;
+                        Ok(res.finish())
+                    }
                 }
             }
         }
-        // No middleware returned Halt, go with the last one in the train
-        res.origin // TODO: migration cleanup - return 404
+    }
+
+    // Run the handlers, turning a panic into an error if asked to.
+    async fn run_handlers(&self, req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        if !self.catch_panics {
+            return self.invoke_handlers(req, res).await;
+        }
+
+        let spare = res.fresh();
+        match AssertUnwindSafe(self.invoke_handlers(req, res)).catch_unwind().await {
+            Ok(result) => result,
+            Err(payload) => {
+                let message = catch_panic::describe(&*payload);
+                error!("{}", message);
+                Err(NickelError::new(spare, message, StatusCode::INTERNAL_SERVER_ERROR))
+            }
+        }
+    }
+
+    async fn invoke_handlers(&self, req: &mut Request<D>, res: Response<D>) -> MiddlewareResult<D> {
+        Next { layers: &self.handlers, fallback: self.fallback.as_deref() }.run(req, res).await
+    }
+
+    pub(crate) fn set_request_timeout(&mut self, timeout: Option<Duration>) {
+        self.request_timeout = timeout;
+    }
+
+    pub(crate) fn set_catch_panics(&mut self, enabled: bool) {
+        if enabled {
+            catch_panic::install_hook();
+        }
+        self.catch_panics = enabled;
+    }
+
+    pub(crate) fn set_last_resort(&mut self, responder: Option<LastResort>) {
+        self.last_resort = responder;
+    }
+
+    // The response for errors that no error handler took care of.
+    fn last_resort(&self, message: &str) -> HyperResponse<Body> {
+        match self.last_resort {
+            Some(ref responder) => responder(message),
+            None => HyperResponse::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from("Internal Server Error"))
+                .unwrap(),
+        }
     }
 
     pub fn new () -> MiddlewareStack<D> {
         MiddlewareStack{
             handlers: Vec::new(),
-            error_handlers: Vec::new()
+            error_handlers: Vec::new(),
+            request_timeout: None,
+            last_resort: None,
+            catch_panics: false,
+            fallback: None,
+        }
+    }
+}
+
+/// The error a request fails with when its connection should be dropped
+/// rather than answered.
+#[derive(Debug)]
+pub struct Aborted(Cow<'static, str>);
+
+impl Error for Aborted {}
+
+impl fmt::Display for Aborted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "connection aborted: {}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Action, Continue, ErrorHandler, Halt, HttpRouter, MiddlewareResult};
+    use crate::{Next, Nickel, NickelError, Options, Request, Response};
+    use crate::middleware::MiddlewareStack;
+    use crate::test_support::{fail, lose_response, read, serve, slow, tag};
+    use async_trait::async_trait;
+    use hyper::StatusCode;
+    use hyper::header::{HeaderName, HeaderValue};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn request_timeout_goes_through_error_handlers() {
+        fn record_timeout(err: &mut NickelError<()>, _req: &mut Request<()>) -> Action {
+            if let Some(ref mut res) = err.stream {
+                if res.status() == StatusCode::SERVICE_UNAVAILABLE {
+                    res.set_header(hyper::header::RETRY_AFTER, hyper::header::HeaderValue::from_static("1"));
+                }
+            }
+            Continue(())
+        }
+
+        let mut server = Nickel::new();
+        server.options = Options::default().request_timeout(Some(Duration::from_millis(100)));
+        server.handle_error(record_timeout);
+        server.get("/slow", slow);
+        server.get("/fast", middleware!("fast"));
+        let server = serve(server).await;
+
+        let res = server.fetch("/slow").await;
+        assert_eq!(res.headers()[hyper::header::RETRY_AFTER], "1");
+        assert_eq!(read(res).await, (StatusCode::SERVICE_UNAVAILABLE, "Service Unavailable".to_string()));
+
+        assert_eq!(server.get("/fast").await.0, StatusCode::OK);
+    }
+
+    async fn echo_length(req: &mut Request, res: Response) -> MiddlewareResult {
+        let body = try_with!(res, req.raw_body().await);
+        res.send(format!("{} bytes", body.len()))
+    }
+
+    async fn count_hits(req: &mut Request, res: Response) -> MiddlewareResult {
+        tokio::task::yield_now().await;
+        req.extensions_mut().insert::<Hits>(1);
+        res.next_middleware()
+    }
+
+    struct Hits;
+
+    impl typemap::Key for Hits { type Value = usize; }
+
+    #[tokio::test]
+    async fn registers_async_handlers() {
+        let mut server = Nickel::new();
+        server.utilize(count_hits);
+        server.post("/echo", echo_length);
+        server.get("/closure", async |req: &mut Request, res: Response| {
+            tokio::task::yield_now().await;
+            let hits = req.extensions().get::<Hits>().copied().unwrap_or(0);
+            res.send(format!("hits: {}", hits))
+        });
+        let server = serve(server).await;
+
+        assert_eq!(server.post("/echo", "hello").await, (StatusCode::OK, "5 bytes".to_string()));
+        assert_eq!(server.get("/closure").await, (StatusCode::OK, "hits: 1".to_string()));
+    }
+
+    struct Tag(&'static str);
+
+    #[async_trait]
+    impl ErrorHandler<()> for Tag {
+        async fn handle_error(&self, err: &mut NickelError<()>, _req: &mut Request<()>) -> Action {
+            tokio::task::yield_now().await;
+            if let Some(ref mut res) = err.stream {
+                let seen = res.headers().get("x-handled")
+                    .map(|v| format!("{},{}", v.to_str().unwrap(), self.0))
+                    .unwrap_or_else(|| self.0.to_string());
+                res.set_header(HeaderName::from_static("x-handled"), HeaderValue::from_str(&seen).unwrap());
+            }
+            Continue(())
         }
     }
+
+    #[tokio::test]
+    async fn awaits_error_handlers_in_reverse_order() {
+        fn teapot(err: &mut NickelError<()>, _req: &mut Request<()>) -> Action {
+            match err.stream {
+                Some(ref mut res) if res.status() == StatusCode::IM_A_TEAPOT => {
+                    res.set_body("short and stout");
+                    Halt(())
+                }
+                _ => Continue(()),
+            }
+        }
+
+        let mut server = Nickel::new();
+        server.handle_error(Tag("first"));
+        server.handle_error(Tag("second"));
+        server.handle_error(teapot);
+        server.get("/teapot", async |_: &mut Request, res: Response| {
+            res.error(StatusCode::IM_A_TEAPOT, "teapot")
+        });
+        let server = serve(server).await;
+
+        let res = server.fetch("/teapot").await;
+        assert!(res.headers().get("x-handled").is_none());
+        assert_eq!(read(res).await, (StatusCode::IM_A_TEAPOT, "short and stout".to_string()));
+
+        let res = server.fetch("/missing").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.headers()["x-handled"], "second,first");
+    }
+
+    fn keep_going(_err: &mut NickelError<()>, _req: &mut Request<()>) -> Action {
+        Continue(())
+    }
+
+    fn halt_anyway(_err: &mut NickelError<()>, _req: &mut Request<()>) -> Action {
+        Halt(())
+    }
+
+    async fn bail(_: &mut Request, res: Response) -> MiddlewareResult {
+        res.bail("giving up")
+    }
+
+    // A server whose only error handler is `handler`, in place of the default
+    fn with_only_error_handler(handler: fn(&mut NickelError<()>, &mut Request<()>) -> Action) -> Nickel {
+        let mut server = Nickel::new();
+        server.middleware_stack = MiddlewareStack::new();
+        server.handle_error(handler);
+        server.get("/fail", fail);
+        server.get("/lost", lose_response);
+        server.get("/bail", bail);
+        server.get("/ok", middleware!("ok"));
+        server
+    }
+
+    #[tokio::test]
+    async fn unhandled_errors_get_a_500() {
+        let server = serve(with_only_error_handler(keep_going)).await;
+
+        // The connection is reused, so it must survive each error
+        let internal_error = (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error".to_string());
+        assert_eq!(server.get("/fail").await, internal_error);
+        assert_eq!(server.get("/lost").await, internal_error);
+        assert_eq!(server.get("/ok").await, (StatusCode::OK, "ok".to_string()));
+    }
+
+    #[tokio::test]
+    async fn halting_without_a_response_gets_a_500() {
+        let mut server = with_only_error_handler(halt_anyway);
+        server.options = Options::default()
+            .last_resort(|message| {
+                hyper::Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(format!("last resort: {}", message).into())
+                    .unwrap()
+            });
+        let server = serve(server).await;
+
+        assert_eq!(server.get("/lost").await,
+                   (StatusCode::INTERNAL_SERVER_ERROR, "last resort: lost the response".to_string()));
+        // Halting with a response still sends it
+        assert_eq!(server.get("/fail").await.0, StatusCode::IM_A_TEAPOT);
+    }
+
+    #[tokio::test]
+    async fn bail_drops_the_connection() {
+        let server = serve(with_only_error_handler(keep_going)).await;
+
+        let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+        stream.write_all(b"GET /bail HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert!(buf.is_empty(), "expected no response, got {:?}", String::from_utf8_lossy(&buf));
+
+        // The server carries on serving everyone else
+        assert_eq!(server.get("/ok").await, (StatusCode::OK, "ok".to_string()));
+    }
+
+    // Turns errors from downstream into responses, and tags everything else
+    async fn recover(req: &mut Request, res: Response, next: Next<'_>) -> MiddlewareResult {
+        match next.run(req, res).await {
+            Ok(Halt(mut res)) => {
+                res.set_header(HeaderName::from_static("x-around"), HeaderValue::from_static("halted"));
+                Ok(Halt(res))
+            }
+            Ok(Continue(res)) => Ok(Continue(res)),
+            Err(NickelError { stream: Some(mut res), message, .. }) => {
+                res.set_body(format!("recovered from {}", message));
+                Ok(Halt(res))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn json_not_found(req: &mut Request, mut res: Response) -> MiddlewareResult {
+        tokio::task::yield_now().await;
+        res.set(StatusCode::NOT_FOUND).set(crate::MediaType::Json);
+        res.send(format!(r#"{{"missing":"{}"}}"#, req.path_without_query()))
+    }
+
+    #[tokio::test]
+    async fn fallback_replaces_the_default_404() {
+        let mut server = Nickel::new();
+        server.around(recover);
+        server.fallback(json_not_found);
+        // Passes everything on, the fallback must still run afterwards
+        server.utilize(tag);
+        server.get("/ok", middleware!("ok"));
+        let server = serve(server).await;
+
+        let res = server.fetch("/nope").await;
+        assert_eq!(res.headers()[hyper::header::CONTENT_TYPE], "application/json");
+        assert_eq!(res.headers()["x-tagged"], "yes");
+        // The around middleware saw the fallback's response
+        assert_eq!(res.headers()["x-around"], "halted");
+        assert_eq!(read(res).await, (StatusCode::NOT_FOUND, r#"{"missing":"/nope"}"#.to_string()));
+
+        assert_eq!(server.get("/ok").await, (StatusCode::OK, "ok".to_string()));
+    }
+
+    #[tokio::test]
+    async fn around_middleware_sees_downstream_results() {
+        let mut server = Nickel::new();
+        // Registered before the around middleware, so outside of it
+        server.get("/early", middleware!("early"));
+        server.around(recover);
+        server.around(async |req: &mut Request, res: Response, next: Next| {
+            let path = req.path_without_query().to_string();
+            let mut result = next.run(req, res).await;
+            if let Ok(Halt(ref mut res)) = result {
+                res.set_header(HeaderName::from_static("x-path"), HeaderValue::from_str(&path).unwrap());
+            }
+            result
+        });
+        server.get("/ok", middleware!("ok"));
+        server.get("/fail", fail);
+        let server = serve(server).await;
+
+        let res = server.fetch("/ok").await;
+        assert_eq!(res.headers()["x-around"], "halted");
+        assert_eq!(res.headers()["x-path"], "/ok");
+
+        let res = server.fetch("/early").await;
+        assert!(res.headers().get("x-around").is_none());
+
+        assert_eq!(server.get("/fail").await, (StatusCode::IM_A_TEAPOT, "recovered from teapot".to_string()));
+    }
 }
@@ -2,11 +2,11 @@ use async_trait::async_trait;
 use crate::nickel::Nickel;
 use crate::request::Request;
 use crate::response::Response;
-use crate::middleware::{Continue, Middleware, MiddlewareResult};
+use crate::middleware::{Continue, IntoMiddleware, Middleware, MiddlewareResult};
 use hyper::Uri;
 
 pub trait Mountable<D: Send + 'static + Sync>: Send + 'static + Sync {
-    fn mount<S: Into<String>, M: Middleware<D>>(&mut self, mount_point: S, middleware: M);
+    fn mount<S: Into<String>, M: IntoMiddleware<D, K>, K>(&mut self, mount_point: S, middleware: M);
 }
 
 impl<D> Mountable<D> for Nickel<D>
@@ -25,8 +25,8 @@ where D: Send + Sync + 'static {
     ///
     /// # Panics
     /// Panics if mount_point does not have a leading and trailing slash.
-    fn mount<S: Into<String>, M: Middleware<D>>(&mut self, mount_point: S, middleware: M) {
-        self.utilize(Mount::new(mount_point, middleware));
+    fn mount<S: Into<String>, M: IntoMiddleware<D, K>, K>(&mut self, mount_point: S, middleware: M) {
+        self.utilize(Mount::new(mount_point, middleware.into_middleware()));
     }
 }
 
@@ -0,0 +1,339 @@
+//! Streaming `multipart/form-data` parsing, for forms with file uploads.
+//!
+//! `Request::multipart` takes the body and returns a `Multipart`, which
+//! yields the parts of the form one at a time as they arrive. A part can be
+//! read in chunks, in full, or spooled to a temporary file, so large uploads
+//! never need to be held in memory.
+//!
+//! # Examples
+//! ```rust
+//! #[macro_use] extern crate nickel;
+//! use nickel::{Nickel, HttpRouter, Request, Response, MiddlewareResult};
+//! use nickel::multipart::MultipartOptions;
+//!
+//! async fn upload(req: &mut Request, res: Response) -> MiddlewareResult {
+//!     let options = MultipartOptions::default()
+//!         .part_limit(Some(10 * 1024 * 1024))
+//!         .total_limit(Some(50 * 1024 * 1024));
+//!     let mut form = try_with!(res, req.multipart_with(options));
+//!
+//!     let mut saved = Vec::new();
+//!     while let Some(part) = try_with!(res, form.next_part().await) {
+//!         if part.is_file() {
+//!             let file = try_with!(res, part.spool().await);
+//!             saved.push(format!("{:?} ({} bytes)", file.file_name(), file.size()));
+//!         }
+//!     }
+//!     res.send(format!("Saved {}", saved.join(", ")))
+//! }
+//!
+//! fn main() {
+//!     let mut server = Nickel::new();
+//!     server.post("/upload", upload);
+//! }
+//! ```
+use crate::body_limit::BodyTooLarge;
+use hyper::{Body, StatusCode};
+use hyper::body::Bytes;
+use hyper::header::HeaderMap;
+use mime::Mime;
+use std::error::Error as StdError;
+use std::io;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
+
+/// Limits and the spooling directory for `Request::multipart_with`.
+///
+/// A part or form over its limit fails with `413 Payload Too Large`. These
+/// are in addition to the request's `body_limit`, which covers the whole
+/// body. By default a part may be up to 10MiB and the whole form up to
+/// 50MiB.
+#[derive(Clone, Debug)]
+pub struct MultipartOptions {
+    part_limit: Option<u64>,
+    total_limit: Option<u64>,
+    temp_dir: Option<PathBuf>,
+}
+
+impl Default for MultipartOptions {
+    fn default() -> MultipartOptions {
+        MultipartOptions {
+            part_limit: Some(10 * 1024 * 1024),
+            total_limit: Some(50 * 1024 * 1024),
+            temp_dir: None,
+        }
+    }
+}
+
+impl MultipartOptions {
+    /// The maximum size in bytes of the data of each part, or no limit for
+    /// `None`. Defaults to 10MiB.
+    pub fn part_limit(mut self, limit: Option<u64>) -> Self {
+        self.part_limit = limit;
+        self
+    }
+
+    /// The maximum size in bytes of the whole form, or no limit for `None`.
+    /// Defaults to 50MiB.
+    pub fn total_limit(mut self, limit: Option<u64>) -> Self {
+        self.total_limit = limit;
+        self
+    }
+
+    /// The directory `Part::spool` writes to. Defaults to
+    /// `std::env::temp_dir()`.
+    pub fn temp_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.temp_dir = Some(dir.into());
+        self
+    }
+}
+
+/// A `multipart/form-data` body, created by `Request::multipart`.
+pub struct Multipart {
+    inner: multer::Multipart<'static>,
+    temp_dir: PathBuf,
+}
+
+impl Multipart {
+    pub(crate) fn new(body: Body, boundary: String, options: MultipartOptions) -> Multipart {
+        let mut limits = multer::SizeLimit::new();
+        if let Some(limit) = options.part_limit {
+            limits = limits.per_field(limit);
+        }
+        if let Some(limit) = options.total_limit {
+            limits = limits.whole_stream(limit);
+        }
+        let constraints = multer::Constraints::new().size_limit(limits);
+        Multipart {
+            inner: multer::Multipart::with_constraints(body, boundary, constraints),
+            temp_dir: options.temp_dir.unwrap_or_else(std::env::temp_dir),
+        }
+    }
+
+    /// The next part of the form, or `None` once all have been read. Any
+    /// data left unread in the previous part is skipped, which requires
+    /// the previous part to have been dropped.
+    pub async fn next_part(&mut self) -> Result<Option<Part>, (StatusCode, String)> {
+        let field = self.inner.next_field().await.map_err(multipart_error)?;
+        Ok(field.map(|inner| Part { inner, temp_dir: self.temp_dir.clone() }))
+    }
+}
+
+/// A field or file in a `multipart/form-data` body.
+pub struct Part {
+    inner: multer::Field<'static>,
+    temp_dir: PathBuf,
+}
+
+impl Part {
+    /// The name of the form field, from the `Content-Disposition` header.
+    pub fn name(&self) -> Option<&str> {
+        self.inner.name()
+    }
+
+    /// The name of the uploaded file, from the `Content-Disposition`
+    /// header. This comes from the client and is not safe to use as a
+    /// path as is.
+    pub fn file_name(&self) -> Option<&str> {
+        self.inner.file_name()
+    }
+
+    /// Whether the part is an uploaded file rather than a plain field.
+    pub fn is_file(&self) -> bool {
+        self.file_name().is_some()
+    }
+
+    /// The `Content-Type` of the part, if given.
+    pub fn content_type(&self) -> Option<&Mime> {
+        self.inner.content_type()
+    }
+
+    /// All the headers of the part.
+    pub fn headers(&self) -> &HeaderMap {
+        self.inner.headers()
+    }
+
+    /// The next chunk of the part's data as it arrives, or `None` at its
+    /// end.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, (StatusCode, String)> {
+        self.inner.chunk().await.map_err(multipart_error)
+    }
+
+    /// Read all the part's data into memory.
+    pub async fn bytes(self) -> Result<Bytes, (StatusCode, String)> {
+        self.inner.bytes().await.map_err(multipart_error)
+    }
+
+    /// Read all the part's data into a `String`, decoded with the charset
+    /// of its content type, UTF-8 by default.
+    pub async fn text(self) -> Result<String, (StatusCode, String)> {
+        self.inner.text().await.map_err(multipart_error)
+    }
+
+    /// Write the part's data to a new temporary file as it arrives. The
+    /// file is deleted when the returned `SpooledFile` is dropped, unless it
+    /// is kept with `SpooledFile::persist`.
+    pub async fn spool(mut self) -> Result<SpooledFile, (StatusCode, String)> {
+        let temp = tempfile::Builder::new()
+            .prefix("nickel-upload-")
+            .tempfile_in(&self.temp_dir)
+            .map_err(internal_error)?;
+        let mut file = tokio::fs::File::from_std(temp.as_file().try_clone().map_err(internal_error)?);
+        let mut size = 0;
+        while let Some(chunk) = self.chunk().await? {
+            file.write_all(&chunk).await.map_err(internal_error)?;
+            size += chunk.len() as u64;
+        }
+        file.flush().await.map_err(internal_error)?;
+        Ok(SpooledFile {
+            name: self.name().map(str::to_string),
+            file_name: self.file_name().map(str::to_string),
+            content_type: self.content_type().cloned(),
+            size,
+            file: temp,
+        })
+    }
+}
+
+/// A part written to a temporary file by `Part::spool`.
+#[derive(Debug)]
+pub struct SpooledFile {
+    name: Option<String>,
+    file_name: Option<String>,
+    content_type: Option<Mime>,
+    size: u64,
+    file: NamedTempFile,
+}
+
+impl SpooledFile {
+    /// The name of the form field the file was uploaded in.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The name of the file as given by the client, see `Part::file_name`.
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    /// The `Content-Type` of the part, if given.
+    pub fn content_type(&self) -> Option<&Mime> {
+        self.content_type.as_ref()
+    }
+
+    /// The size of the file in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Where the file is stored while it's temporary.
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+
+    /// Move the file to `path`, so it isn't deleted.
+    pub fn persist<P: AsRef<Path>>(self, path: P) -> io::Result<()> {
+        self.file.persist(path).map(|_| ()).map_err(|e| e.error)
+    }
+}
+
+fn internal_error(e: io::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+fn multipart_error(e: multer::Error) -> (StatusCode, String) {
+    match e {
+        multer::Error::FieldSizeExceeded { .. } | multer::Error::StreamSizeExceeded { .. } => {
+            (StatusCode::PAYLOAD_TOO_LARGE, e.to_string())
+        },
+        multer::Error::StreamReadFailed(ref cause) => match body_too_large(&**cause) {
+            Some(too_large) => (StatusCode::PAYLOAD_TOO_LARGE, too_large.to_string()),
+            None => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        },
+        _ => (StatusCode::BAD_REQUEST, e.to_string()),
+    }
+}
+
+// The request's body limit is enforced by the body stream, and multer wraps
+// the stream's errors in its own, which don't give their source.
+fn body_too_large<'a>(err: &'a (dyn StdError + 'static)) -> Option<&'a BodyTooLarge> {
+    if let Some(too_large) = err.downcast_ref::<BodyTooLarge>() {
+        return Some(too_large);
+    }
+    match err.downcast_ref::<multer::Error>() {
+        Some(multer::Error::StreamReadFailed(cause)) => body_too_large(&**cause),
+        _ => err.source().and_then(body_too_large),
+    }
+}
+
+#[test]
+fn default_options_limit_uploads() {
+    let options = MultipartOptions::default();
+    assert_eq!(options.part_limit, Some(10 * 1024 * 1024));
+    assert_eq!(options.total_limit, Some(50 * 1024 * 1024));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MultipartOptions;
+    use crate::{BodyLimit, HttpRouter, MiddlewareResult, Nickel, Request, Response};
+    use crate::test_support::{read, serve};
+    use hyper::StatusCode;
+
+    async fn upload(req: &mut Request, res: Response) -> MiddlewareResult {
+        let limit = req.origin.headers().get("x-part-limit")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok());
+        let mut form = try_with!(res, req.multipart_with(MultipartOptions::default().part_limit(limit)));
+        let mut seen = Vec::new();
+        while let Some(part) = try_with!(res, form.next_part().await) {
+            let name = part.name().unwrap_or("").to_string();
+            if part.is_file() {
+                let file = try_with!(res, part.spool().await);
+                let contents = std::fs::read_to_string(file.path()).unwrap();
+                seen.push(format!("{}={:?} {} {} bytes: {}", name, file.file_name().unwrap(),
+                                  file.content_type().unwrap(), file.size(), contents));
+            } else {
+                seen.push(format!("{}={}", name, try_with!(res, part.text().await)));
+            }
+        }
+        // the body has been taken
+        assert!(req.raw_body().await.is_err());
+        res.send(seen.join("\n"))
+    }
+
+    #[tokio::test]
+    async fn streams_multipart_uploads() {
+        let mut server = Nickel::new();
+        server.post("/upload", upload);
+        server.post("/small", BodyLimit::new(Some(64), upload));
+        let server = serve(server).await;
+
+        let body = "--XyZ\r\n\
+                    Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+                    Holiday\r\n\
+                    --XyZ\r\n\
+                    Content-Disposition: form-data; name=\"photo\"; filename=\"beach.txt\"\r\n\
+                    Content-Type: text/plain\r\n\r\n\
+                    sand and sea\r\n\
+                    --XyZ--\r\n";
+        let client = &server;
+        let send = |path: &str, content_type: &str, part_limit: &str| {
+            let req = hyper::Request::post(server.url(path))
+                .header("content-type", content_type)
+                .header("x-part-limit", part_limit)
+                .body(hyper::Body::from(body))
+                .unwrap();
+            async move { read(client.send(req).await).await }
+        };
+        let multipart = "multipart/form-data; boundary=XyZ";
+
+        assert_eq!(send("/upload", multipart, "").await,
+                   (StatusCode::OK, "title=Holiday\nphoto=\"beach.txt\" text/plain 12 bytes: sand and sea".to_string()));
+        assert_eq!(send("/upload", multipart, "8").await.0, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(send("/small", multipart, "").await.0, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(send("/upload", "multipart/form-data", "").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(send("/upload", "text/plain", "").await.0, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+}
@@ -1,17 +1,27 @@
-use std::net::ToSocketAddrs;
+use std::net::{TcpListener, ToSocketAddrs};
+#[cfg(unix)]
+use std::path::Path;
 use std::time::Duration;
-use std::env;
 use std::error::Error as StdError;
+use crate::activation;
+use crate::endpoint::Endpoint;
+use crate::limits::{OverloadPolicy, ServerStats};
 use crate::router::{Router, HttpRouter, Matcher};
-use crate::middleware::{MiddlewareStack, Middleware, ErrorHandler};
-use crate::server::Server;
+use crate::middleware::{MiddlewareStack, Middleware, IntoMiddleware, ErrorHandler, LastResort};
+use crate::middleware::AroundMiddleware;
+use crate::server::{Server, ListeningServer};
+#[cfg(feature = "tower")]
+use crate::service::{self, BoxError, NickelService, ServiceLayer};
 use crate::template_cache::ReloadPolicy;
-use hyper::{Method, StatusCode};
-//use hyper::net::SslServer;
+use hyper::{Body, Method, Response as HyperResponse, StatusCode};
+#[cfg(feature = "ssl")]
+use crate::tls::TlsConfig;
 
 //pre defined middleware
 use crate::default_error_handler::DefaultErrorHandler;
 
+type OnListen = Box<dyn FnOnce(&Endpoint) + Send + Sync>;
+
 /// Configuration options for the server.
 ///
 /// This struct provides a builder-style API for constructing the desired options.
@@ -21,20 +31,39 @@ use crate::default_error_handler::DefaultErrorHandler;
 /// use nickel::{Nickel, Options};
 /// let mut server = Nickel::new();
 ///
-/// // Don't print to stdout when starting the server
+/// // Don't log the address when starting the server
 /// // and force using 8 threads.
 /// server.options = Options::default()
 ///                      .output_on_listen(false)
 ///                      .thread_count(Some(8));
 /// ```
 pub struct Options {
-    output_on_listen: bool,
-    thread_count: Option<usize>,
-    reload_policy: ReloadPolicy,
+    pub(crate) output_on_listen: bool,
+    pub(crate) on_listen: Option<OnListen>,
+    pub(crate) thread_count: Option<usize>,
+    pub(crate) reload_policy: ReloadPolicy,
+    pub(crate) keep_alive_timeout: Option<Duration>,
+    pub(crate) shutdown_on_signal: bool,
+    pub(crate) remove_stale_socket: bool,
+    pub(crate) http2: bool,
+    pub(crate) http2_max_concurrent_streams: Option<u32>,
+    pub(crate) http2_initial_stream_window_size: Option<u32>,
+    pub(crate) http2_initial_connection_window_size: Option<u32>,
+    pub(crate) http2_adaptive_window: bool,
+    pub(crate) request_timeout: Option<Duration>,
+    pub(crate) catch_panics: bool,
+    pub(crate) header_read_timeout: Option<Duration>,
+    pub(crate) max_body_size: Option<usize>,
+    pub(crate) max_connections: Option<usize>,
+    pub(crate) max_in_flight_requests: Option<usize>,
+    pub(crate) overload_policy: OverloadPolicy,
+    pub(crate) last_resort: Option<LastResort>,
 }
 
 impl Options {
-    /// Whether the server should print the local address it is listening on when starting.
+    /// Whether the server should log the local address it is listening on
+    /// when starting. The message is logged at `info` level through the
+    /// `log` crate.
     ///
     /// Defaults to `true`.
     pub fn output_on_listen(mut self, output: bool) -> Self {
@@ -42,8 +71,39 @@ impl Options {
         self
     }
 
-    /// The number of threads the server should use or `None` to use the
-    /// `hyper`'s default of `1.25 * core_count`.
+    /// A callback to run once the server is bound, receiving the address it
+    /// is listening on. Useful to find the port that was assigned when
+    /// binding to port 0.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// use nickel::{Nickel, Options};
+    /// use tokio::sync::oneshot;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let (tx, rx) = oneshot::channel();
+    ///     let mut server = Nickel::new();
+    ///     server.options = Options::default().on_listen(move |addr| {
+    ///         let _ = tx.send(addr.clone());
+    ///     });
+    ///
+    ///     let listening = server.start("127.0.0.1:0").await.unwrap();
+    ///     println!("Started on {}", rx.await.unwrap());
+    ///     listening.wait().await.unwrap();
+    /// }
+    /// ```
+    pub fn on_listen<F>(mut self, callback: F) -> Self
+    where F: FnOnce(&Endpoint) + Send + Sync + 'static {
+        self.on_listen = Some(Box::new(callback));
+        self
+    }
+
+    /// The number of worker threads the server should use, or `None` to
+    /// serve connections on the runtime the server was started from.
+    ///
+    /// When set, the server runs on a dedicated multi-threaded tokio
+    /// runtime with that many workers.
     ///
     /// Defaults to `None`.
     pub fn thread_count(mut self, thread_count: Option<usize>) -> Self {
@@ -56,14 +116,217 @@ impl Options {
         self.reload_policy = reload_policy;
         self
     }
+
+    /// The timeout for idle keep-alive connections, see
+    /// `Nickel::keep_alive_timeout`.
+    ///
+    /// Defaults to 75 seconds.
+    pub fn keep_alive_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.keep_alive_timeout = timeout;
+        self
+    }
+
+    /// Whether the server should shut down gracefully when the process
+    /// receives Ctrl-C (or `SIGTERM` on unix). In-flight requests are
+    /// allowed to finish before the server stops.
+    ///
+    /// Defaults to `false`.
+    pub fn shutdown_on_signal(mut self, shutdown: bool) -> Self {
+        self.shutdown_on_signal = shutdown;
+        self
+    }
+
+    /// Whether `Nickel::listen_unix` should remove a socket file left
+    /// behind by a previous process before binding. The file is only
+    /// removed if it is a socket that nothing is listening on.
+    ///
+    /// Defaults to `false`.
+    pub fn remove_stale_socket(mut self, remove: bool) -> Self {
+        self.remove_stale_socket = remove;
+        self
+    }
+
+    /// Whether the server should accept HTTP/2 connections alongside
+    /// HTTP/1.
+    ///
+    /// Over TLS, HTTP/2 is negotiated with ALPN. On plaintext connections
+    /// clients must use prior knowledge (h2c), starting the connection with
+    /// the HTTP/2 preface; upgrading from HTTP/1 is not supported.
+    ///
+    /// Defaults to `false`.
+    pub fn http2(mut self, enabled: bool) -> Self {
+        self.http2 = enabled;
+        self
+    }
+
+    /// The maximum number of concurrent streams a client may open on an
+    /// HTTP/2 connection, or `None` for no limit.
+    ///
+    /// Defaults to `None`.
+    pub fn http2_max_concurrent_streams(mut self, max: Option<u32>) -> Self {
+        self.http2_max_concurrent_streams = max;
+        self
+    }
+
+    /// The initial flow control window size in bytes for each HTTP/2
+    /// stream, or `None` for the protocol default of 65,535.
+    ///
+    /// Defaults to `None`.
+    pub fn http2_initial_stream_window_size(mut self, size: Option<u32>) -> Self {
+        self.http2_initial_stream_window_size = size;
+        self
+    }
+
+    /// The initial flow control window size in bytes for each HTTP/2
+    /// connection, or `None` for the protocol default of 65,535.
+    ///
+    /// Defaults to `None`.
+    pub fn http2_initial_connection_window_size(mut self, size: Option<u32>) -> Self {
+        self.http2_initial_connection_window_size = size;
+        self
+    }
+
+    /// Whether to size HTTP/2 flow control windows adaptively based on the
+    /// measured bandwidth-delay product. Overrides the initial window sizes.
+    ///
+    /// Defaults to `false`.
+    pub fn http2_adaptive_window(mut self, enabled: bool) -> Self {
+        self.http2_adaptive_window = enabled;
+        self
+    }
+
+    /// The maximum time the middleware stack may take to handle a request.
+    ///
+    /// When it elapses the handlers are cancelled and a `NickelError` with
+    /// `503 Service Unavailable` is passed to the error handlers in their
+    /// place.
+    ///
+    /// Defaults to `None`, no limit.
+    pub fn request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Whether a panic in a handler is caught and passed to the error
+    /// handlers as a `NickelError` with `500 Internal Server Error`, with
+    /// the panic message and location as its message. Otherwise the
+    /// connection is dropped.
+    ///
+    /// Catching panics replaces the process-wide panic hook with one that
+    /// records the panic location before calling the previous hook.
+    ///
+    /// Defaults to `true`.
+    pub fn catch_panics(mut self, enabled: bool) -> Self {
+        self.catch_panics = enabled;
+        self
+    }
+
+    /// The maximum time a client may take to send the headers of an
+    /// HTTP/1 request before the connection is closed. This guards against
+    /// clients that hold connections open by sending headers very slowly.
+    ///
+    /// The timer also runs while a keep-alive connection waits for its next
+    /// request, so it should not be shorter than the keep-alive timeout you
+    /// want to allow.
+    ///
+    /// Defaults to `None`, no limit.
+    pub fn header_read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.header_read_timeout = timeout;
+        self
+    }
+
+    /// The maximum size in bytes of a request body, or `None` for no limit.
+    ///
+    /// Bodies read with `Request::raw_body` (and the parsers built on it)
+    /// that are larger fail with `413 Payload Too Large`, checked against
+    /// `Content-Length` before reading and again while the body streams
+    /// in. Use `BodyLimit` to override the limit for a single route.
+    ///
+    /// Defaults to `None`.
+    pub fn max_body_size(mut self, limit: Option<usize>) -> Self {
+        self.max_body_size = limit;
+        self
+    }
+
+    /// The maximum number of client connections to serve at once, or
+    /// `None` for no limit. See `overload_policy` for what happens to
+    /// connections beyond the limit.
+    ///
+    /// Defaults to `None`.
+    pub fn max_connections(mut self, max: Option<usize>) -> Self {
+        self.max_connections = max;
+        self
+    }
+
+    /// The maximum number of requests the middleware may handle at once, or
+    /// `None` for no limit. See `overload_policy` for what happens to
+    /// requests beyond the limit.
+    ///
+    /// Defaults to `None`.
+    pub fn max_in_flight_requests(mut self, max: Option<usize>) -> Self {
+        self.max_in_flight_requests = max;
+        self
+    }
+
+    /// Whether connections and requests beyond `max_connections` and
+    /// `max_in_flight_requests` wait for capacity or are rejected.
+    ///
+    /// Defaults to `OverloadPolicy::Queue`.
+    pub fn overload_policy(mut self, policy: OverloadPolicy) -> Self {
+        self.overload_policy = policy;
+        self
+    }
+
+    /// Build the response for errors that none of the error handlers halted
+    /// on, or that were halted on without a `Response` to send. The
+    /// callback gets the error message, which is best kept from clients.
+    ///
+    /// Defaults to a plain `500 Internal Server Error`.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use nickel::{Nickel, Options};
+    /// use nickel::hyper::{Body, Response, StatusCode};
+    ///
+    /// let mut server = Nickel::new();
+    /// server.options = Options::default().last_resort(|_message| {
+    ///     Response::builder()
+    ///         .status(StatusCode::INTERNAL_SERVER_ERROR)
+    ///         .body(Body::from("<h1>Something went wrong</h1>"))
+    ///         .unwrap()
+    /// });
+    /// ```
+    pub fn last_resort<F>(mut self, responder: F) -> Self
+            where F: Fn(&str) -> HyperResponse<Body> + Send + Sync + 'static {
+        self.last_resort = Some(Box::new(responder));
+        self
+    }
 }
 
 impl Default for Options {
     fn default() -> Self {
         Options {
             output_on_listen: true,
+            on_listen: None,
             thread_count: None,
             reload_policy: ReloadPolicy::Never,
+            // Default value from nginx
+            keep_alive_timeout: Some(Duration::from_secs(75)),
+            shutdown_on_signal: false,
+            remove_stale_socket: false,
+            http2: false,
+            http2_max_concurrent_streams: None,
+            http2_initial_stream_window_size: None,
+            http2_initial_connection_window_size: None,
+            http2_adaptive_window: false,
+            request_timeout: None,
+            catch_panics: true,
+            header_read_timeout: None,
+            max_body_size: None,
+            max_connections: None,
+            max_in_flight_requests: None,
+            overload_policy: OverloadPolicy::Queue,
+            last_resort: None,
         }
     }
 }
@@ -71,12 +334,16 @@ impl Default for Options {
 /// Nickel is the application object. It's the surface that
 /// holds all public APIs.
 pub struct Nickel<D: Sync + Send + 'static = ()> {
-    middleware_stack: MiddlewareStack<D>,
+    pub(crate) middleware_stack: MiddlewareStack<D>,
     data: D,
-    keep_alive_timeout: Option<Duration>,
 
     /// Configuration options for the server.
     pub options: Options,
+
+    stats: ServerStats,
+
+    #[cfg(feature = "tower")]
+    layer: Option<ServiceLayer<D>>,
 }
 
 impl<D: Sync + Send + 'static> HttpRouter<D> for Nickel<D> {
@@ -111,12 +378,19 @@ impl<D: Sync + Send + 'static> Nickel<D> {
         // they don't like the default behaviour.
         middleware_stack.add_error_handler(DefaultErrorHandler);
 
+        // Requests nothing else halts on are not found, unless the user
+        // replaces this with a fallback of their own.
+        middleware_stack.set_fallback(middleware! {
+            (StatusCode::NOT_FOUND, "File Not Found")
+        });
+
         Nickel {
             middleware_stack: middleware_stack,
             options: options,
             data: data,
-            // Default value from nginx
-            keep_alive_timeout: Some(Duration::from_secs(75)),
+            stats: ServerStats::default(),
+            #[cfg(feature = "tower")]
+            layer: None,
         }
     }
 
@@ -147,8 +421,55 @@ impl<D: Sync + Send + 'static> Nickel<D> {
     /// });
     /// # }
     /// ```
-    pub fn utilize<T: Middleware<D>>(&mut self, handler: T){
-        self.middleware_stack.add_middleware(handler);
+    pub fn utilize<T: IntoMiddleware<D, K>, K>(&mut self, handler: T){
+        self.middleware_stack.add_middleware(handler.into_middleware());
+    }
+
+    /// Sets the handler for requests that no middleware halted on, in place
+    /// of the default `404 Not Found` error. It runs after all the other
+    /// middleware, inside any `around` middleware, and can be an async
+    /// function.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use nickel::{Nickel, Request, Response, MiddlewareResult, MediaType};
+    /// use nickel::status::StatusCode;
+    ///
+    /// async fn not_found(req: &mut Request, mut res: Response) -> MiddlewareResult {
+    ///     let body = format!(r#"{{"error":"no route for {}"}}"#, req.path_without_query());
+    ///     res.set(StatusCode::NOT_FOUND).set(MediaType::Json);
+    ///     res.send(body)
+    /// }
+    ///
+    /// let mut server = Nickel::new();
+    /// server.fallback(not_found);
+    /// ```
+    pub fn fallback<T: IntoMiddleware<D, K>, K>(&mut self, handler: T) {
+        self.middleware_stack.set_fallback(handler.into_middleware());
+    }
+
+    /// Registers a middleware that runs around all the middleware
+    /// registered after it, and can see and change their result.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use nickel::{Nickel, Request, Response, Next, Halt};
+    /// use nickel::hyper::header::{HeaderName, HeaderValue};
+    ///
+    /// let mut server = Nickel::new();
+    /// server.around(async |req: &mut Request, res: Response, next: Next| {
+    ///     match next.run(req, res).await? {
+    ///         Halt(mut res) => {
+    ///             res.set_header(HeaderName::from_static("x-powered-by"),
+    ///                            HeaderValue::from_static("nickel"));
+    ///             Ok(Halt(res))
+    ///         }
+    ///         unhalted => Ok(unhalted),
+    ///     }
+    /// });
+    /// ```
+    pub fn around<T: AroundMiddleware<D>>(&mut self, handler: T) {
+        self.middleware_stack.add_around(handler);
     }
 
     /// Registers an error handler which will be invoked among other error handler
@@ -157,6 +478,10 @@ impl<D: Sync + Send + 'static> Nickel<D> {
     /// A error handler is nearly identical to a regular middleware handler with the only
     /// difference that it takes an additional error parameter or type `NickelError.
     ///
+    /// Error handlers are awaited in reverse order of registration until one
+    /// of them returns `Halt`. Implement `ErrorHandler` for handlers that need
+    /// to await, or pass a plain function as below.
+    ///
     /// # Examples
     ///
     /// ```{rust}
@@ -213,6 +538,9 @@ impl<D: Sync + Send + 'static> Nickel<D> {
 
     /// Bind and listen for connections on the given host and port.
     ///
+    /// If a supervisor passed a listening socket to the process (see
+    /// `listen_fds`), that socket is served instead and `addr` is ignored.
+    ///
     /// # Examples
     /// ```rust,no_run
     /// use nickel::Nickel;
@@ -223,151 +551,238 @@ impl<D: Sync + Send + 'static> Nickel<D> {
     ///     server.listen("127.0.0.1:6767").await.expect("Failed to launch server");
     /// }
     /// ```
-    pub async fn listen<T: ToSocketAddrs>(mut self, addr: T) -> Result<(), Box<dyn StdError>> {
-        self.middleware_stack.add_middleware(middleware! {
-            (StatusCode::NOT_FOUND, "File Not Found")
-        });
+    pub async fn listen<T: ToSocketAddrs>(self, addr: T) -> Result<(), Box<dyn StdError>> {
+        self.start(addr).await?.wait().await
+    }
 
-        let server = Server::new(self.middleware_stack, self.options.reload_policy, self.data);
-
-        let is_test_harness = env::var_os("NICKEL_TEST_HARNESS").is_some();
-
-        if is_test_harness {
-            // If we're under a test harness, we'll pass zero to get assigned a random
-            // port. See http://doc.rust-lang.org/std/net/struct.TcpListener.html#method.bind
-            server.serve("localhost:0",
-                         self.keep_alive_timeout,
-                         self.options.thread_count).await?
-        } else {
-            // TODO: fixme
-            // if self.options.output_on_listen {
-            //     println!("Listening on http://{}", addr);
-            // }
-            server.serve(addr,
-                         self.keep_alive_timeout,
-                         self.options.thread_count).await?
-        };
-
-        if self.options.output_on_listen {
-            println!("Ctrl-C to shutdown server");
+    /// Bind to the given host and port and serve connections in the
+    /// background, returning a handle to the running server.
+    ///
+    /// The handle can be used to find the bound address, for example when
+    /// binding to port 0, and to shut the server down gracefully.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// use nickel::Nickel;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let server = Nickel::new();
+    ///     let listening = server.start("127.0.0.1:0").await.unwrap();
+    ///     println!("Listening on {}", listening.local_addr());
+    ///
+    ///     // ... later
+    ///     listening.shutdown();
+    ///     listening.wait().await.unwrap();
+    /// }
+    /// ```
+    pub async fn start<T: ToSocketAddrs>(self, addr: T) -> Result<ListeningServer, Box<dyn StdError>> {
+        let server = self.into_server();
+
+        match activation::activated_listener()? {
+            Some(listener) => server.start_on(listener).await,
+            None => server.start(addr).await,
         }
+    }
+
+    /// Bind and listen for connections on the given host and port.
+    /// Only accepts TLS connections, which are terminated with `tls`.
+    ///
+    /// Requests served this way report `true` from `Request::is_secure`.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// use nickel::{Nickel, TlsConfig};
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let server = Nickel::new();
+    ///     let tls = TlsConfig::from_pem_files("foo.crt", "key.pem").unwrap();
+    ///     server.listen_https("127.0.0.1:6767", tls).await.unwrap();
+    /// }
+    /// ```
+    #[cfg(feature = "ssl")]
+    pub async fn listen_https<T: ToSocketAddrs>(self, addr: T, tls: TlsConfig) -> Result<(), Box<dyn StdError>> {
+        self.start_https(addr, tls).await?.wait().await
+    }
+
+    /// Like `start`, but only accepts TLS connections. See `listen_https`.
+    #[cfg(feature = "ssl")]
+    pub async fn start_https<T: ToSocketAddrs>(self, addr: T, tls: TlsConfig) -> Result<ListeningServer, Box<dyn StdError>> {
+        let server = self.into_server();
+
+        match activation::activated_listener()? {
+            Some(listener) => server.start_https_on(listener, tls).await,
+            None => server.start_https(addr, tls).await,
+        }
+    }
+
+    /// Listen for connections on a socket that has already been bound, for
+    /// example one inherited from a previous process during a restart.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// use nickel::Nickel;
+    /// use std::net::TcpListener;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let listener = TcpListener::bind("127.0.0.1:6767").unwrap();
+    ///     Nickel::new().listen_on(listener).await.unwrap();
+    /// }
+    /// ```
+    pub async fn listen_on(self, listener: TcpListener) -> Result<(), Box<dyn StdError>> {
+        self.start_on(listener).await?.wait().await
+    }
+
+    /// Like `start`, but serves an already bound socket. See `listen_on`.
+    pub async fn start_on(self, listener: TcpListener) -> Result<ListeningServer, Box<dyn StdError>> {
+        self.into_server().start_on(listener).await
+    }
+
+    /// Bind and listen for connections on a Unix domain socket at `path`.
+    ///
+    /// Requests served this way report an `Endpoint::Unix` from
+    /// `Request::remote_addr`. See `Options::remove_stale_socket` for
+    /// dealing with socket files left over from a previous run.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// use nickel::{Nickel, Options};
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut server = Nickel::new();
+    ///     server.options = Options::default().remove_stale_socket(true);
+    ///     server.listen_unix("/run/nickel/app.sock").await.unwrap();
+    /// }
+    /// ```
+    #[cfg(unix)]
+    pub async fn listen_unix<P: AsRef<Path>>(self, path: P) -> Result<(), Box<dyn StdError>> {
+        self.start_unix(path).await?.wait().await
+    }
 
-        Ok(())
+    /// Like `start`, but binds a Unix domain socket. See `listen_unix`.
+    #[cfg(unix)]
+    pub async fn start_unix<P: AsRef<Path>>(self, path: P) -> Result<ListeningServer, Box<dyn StdError>> {
+        self.into_server().start_unix(path).await
     }
 
     /// Set the timeout for the keep-alive loop
     ///
-    /// # Performance
+    /// A connection that has been idle for this long, with no request in
+    /// progress, is closed. Setting this to `None` disables keep-alive, so
+    /// each connection is closed after a single response.
     ///
-    /// Setting this to `None` can have significant performance impact, but if
-    /// you need to use a version of rustc < 1.4, then it may be a good choice.
+    /// # Performance
     ///
-    /// Alternatively, setting this too high, can lead to thread exhaustion,
-    /// see [this thread](https://github.com/hyperium/hyper/issues/368) for more.
+    /// Setting this too high can leave many idle connections holding on to
+    /// file descriptors, see
+    /// [this thread](https://github.com/hyperium/hyper/issues/368) for more.
     ///
     /// # Default
     ///
     /// The default value is 75 seconds.
     pub fn keep_alive_timeout(&mut self, timeout: Option<Duration>){
-        self.keep_alive_timeout = timeout;
+        self.options.keep_alive_timeout = timeout;
     }
 
-    /*
-    /// Bind and listen for connections on the given host and port.
-    /// Only accepts SSL connections
+    /// Counters for the connections and requests the server is handling,
+    /// for use in health checks. See `ServerStats`.
+    pub fn stats(&self) -> ServerStats {
+        self.stats.clone()
+    }
+
+    /// Wrap the application in a tower `Layer`, such as those from
+    /// tower-http, when it is served. Combine several layers into one with
+    /// `tower::ServiceBuilder`. See `Server::layer`.
     ///
-    /// # Panics
+    /// # Examples
+    /// ```rust
+    /// use nickel::Nickel;
+    /// use nickel::hyper::{Body, Response};
+    /// use nickel::hyper::header::{HeaderName, HeaderValue};
+    /// use tower::util::MapResponseLayer;
     ///
-    /// Panics if `addr` is an invalid `SocketAddr`.
+    /// let mut server = Nickel::new();
+    /// server.layer(MapResponseLayer::new(|mut res: Response<Body>| {
+    ///     res.headers_mut().insert(HeaderName::from_static("x-powered-by"),
+    ///                              HeaderValue::from_static("nickel"));
+    ///     res
+    /// }));
+    /// ```
+    #[cfg(feature = "tower")]
+    pub fn layer<L, B>(&mut self, layer: L)
+    where L: tower::Layer<NickelService<D>> + Send + Sync + 'static,
+          L::Service: tower::Service<hyper::Request<Body>, Response = HyperResponse<B>> + Clone + Send + 'static,
+          <L::Service as tower::Service<hyper::Request<Body>>>::Error: Into<BoxError>,
+          <L::Service as tower::Service<hyper::Request<Body>>>::Future: Send + 'static,
+          B: hyper::body::HttpBody + Send + 'static,
+          B::Data: Send,
+          B::Error: Into<BoxError>,
+    {
+        self.layer = Some(service::service_layer(layer));
+    }
+
+    /// Turn the application into a tower `Service`, for running inside
+    /// another hyper or tower based server. See `NickelService`.
     ///
     /// # Examples
-    /// ```{rust,no_run}
-    /// # extern crate nickel;
-    /// extern crate hyper;
+    /// ```rust,no_run
+    /// #[macro_use] extern crate nickel;
+    /// use nickel::{Nickel, HttpRouter};
+    /// use nickel::hyper::Server;
+    /// use nickel::hyper::server::conn::AddrStream;
+    /// use nickel::hyper::service::make_service_fn;
+    /// use std::convert::Infallible;
     ///
-    /// # #[cfg(feature = "ssl")]
-    /// use nickel::Nickel;
-    /// # #[cfg(feature = "ssl")]
-    /// use hyper::net::Openssl;
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut app = Nickel::new();
+    ///     app.get("/", middleware!("Hello from nickel"));
+    ///     let app = app.into_service();
     ///
-    /// # #[cfg(feature = "ssl")]
-    /// # fn main() {
-    /// let server = Nickel::new();
-    /// let ssl = Openssl::with_cert_and_key("foo.crt", "key.pem").unwrap();
-    /// server.listen_https("127.0.0.1:6767", ssl).unwrap();
-    /// # }
-    /// # #[cfg(not(feature = "ssl"))]
-    /// # fn main() {}
+    ///     let make_svc = make_service_fn(move |conn: &AddrStream| {
+    ///         let app = app.clone().remote_addr(Some(conn.remote_addr().into()));
+    ///         async move { Ok::<_, Infallible>(app) }
+    ///     });
+    ///     Server::bind(&([127, 0, 0, 1], 6767).into()).serve(make_svc).await.unwrap();
+    /// }
     /// ```
-     */
-    //
-    // Ssl support changed in hyper 0.11
-    //
-    // pub fn listen_https<T,S>(mut self, addr: T, ssl: S) -> Result<ListeningServer, Box<dyn StdError>>
-    // where T: ToSocketAddrs,
-    //       S: SslServer + Send + Clone + 'static {
-    //     self.middleware_stack.add_middleware(middleware! {
-    //         (StatusCode::NOT_FOUND, "File Not Found")
-    //     });
-
-    //     let server = Server::new(self.middleware_stack, self.options.reload_policy, self.data);
-
-    //     let is_test_harness = env::var_os("NICKEL_TEST_HARNESS").is_some();
-
-    //     let listener = if is_test_harness {
-    //         // If we're under a test harness, we'll pass zero to get assigned a random
-    //         // port. See http://doc.rust-lang.org/std/net/struct.TcpListener.html#method.bind
-    //         server.serve_https("localhost:0",
-    //                            self.keep_alive_timeout,
-    //                            self.options.thread_count,
-    //                            ssl)?
-    //     } else {
-    //         server.serve_https(addr,
-    //                            self.keep_alive_timeout,
-    //                            self.options.thread_count,
-    //                            ssl)?
-    //     };
-
-    //     if self.options.output_on_listen {
-    //         println!("Listening on https://{}", listener.socket());
-    //         println!("Ctrl-C to shutdown server");
-    //     }
-
-    //     Ok(listener)
-    // }
-}
-
-// The first test here is just testing the standard library. The other two
-// aren't currently supported. The second is kind of just a std lib test too.
-
-// TODO: migration cleanup - The last two tests are commented out since we no
-// longer provide access to the underlying server. Do we need to?
-
-// #[cfg(test)]
-// mod tests {
-//     use crate::Nickel;
-//     use std::str::FromStr;
-//     use std::net::SocketAddr;
+    #[cfg(feature = "tower")]
+    pub fn into_service(self) -> NickelService<D> {
+        self.into_server().into_service()
+    }
 
-//     #[tokio::test]
-//     #[should_panic(expected = "invalid socket address")]
-//     async fn invalid_listen_addr() {
-//         Nickel::new().listen("127.0.0.1.6667").await.unwrap();
-//     }
+    fn into_server(self) -> Server<D> {
+        #[allow(unused_mut)]
+        let mut server = Server::new(self.middleware_stack, self.data, self.options, self.stats);
+        #[cfg(feature = "tower")]
+        server.set_layer(self.layer);
+        server
+    }
+}
 
-//     // #[test]
-//     // fn can_get_server_address() {
-//     //     let server = Nickel::new().listen("127.0.0.1:12345").await.unwrap();
+#[cfg(test)]
+mod tests {
+    use crate::{Nickel, Options};
+    use crate::test_support::serve;
 
-//     //     assert_eq!(server.socket(), SocketAddr::from_str("127.0.0.1:12345").unwrap());
-//     //     server.detach();
-//     // }
+    #[tokio::test]
+    async fn invalid_listen_addr() {
+        let server = Nickel::new();
+        assert!(server.start("127.0.0.1.6667").await.is_err());
+    }
 
-//     // #[test]
-//     // fn can_get_server_address_with_random_port() {
-//     //     let server = Nickel::new().listen("127.0.0.1:0").await.unwrap();
+    #[tokio::test]
+    async fn on_listen_receives_bound_address() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut server = Nickel::new();
+        server.options = Options::default().on_listen(move |addr| tx.send(addr.clone()).unwrap());
+        let server = serve(server).await;
 
-//     //     assert_eq!(server.socket().ip().to_string(), "127.0.0.1");
-//     //     server.detach();
-//     // }
-// }
+        let addr = rx.try_recv().unwrap();
+        assert_eq!(&addr, server.listening().local_addr());
+        assert_ne!(addr.tcp().unwrap().port(), 0);
+    }
+}
@@ -8,7 +8,9 @@ use crate::response::Response;
 /// One can pattern match against the `kind` property to handle the different cases.
 pub struct NickelError<D: Send + 'static + Sync = ()> {
     pub stream: Option<Response<D>>,
-    pub message: Cow<'static, str>
+    pub message: Cow<'static, str>,
+    // Set by `Response::bail` to drop the connection instead of responding
+    bail: bool,
 }
 
 impl<D: Send + 'static + Sync> NickelError<D> {
@@ -40,6 +42,7 @@ impl<D: Send + 'static + Sync> NickelError<D> {
         NickelError {
             stream: Some(stream),
             message: message.into(),
+            bail: false,
         }
     }
 
@@ -57,9 +60,24 @@ impl<D: Send + 'static + Sync> NickelError<D> {
         NickelError {
             stream: None,
             message: message.into(),
+            bail: false,
         }
     }
 
+    /// An error that drops the connection without sending a response.
+    pub(crate) fn bail<T>(message: T) -> NickelError<D>
+            where T: Into<Cow<'static, str>> {
+        NickelError {
+            stream: None,
+            message: message.into(),
+            bail: true,
+        }
+    }
+
+    pub(crate) fn is_bail(&self) -> bool {
+        self.bail
+    }
+
     pub fn end(self) -> Option<io::Result<()>> {
         self.stream.map(|s| s.end())
     }
@@ -4,14 +4,23 @@ use crate::router::RouteResult;
 //use plugin::{Extensible, Pluggable};
 
 use typemap::{ShareMap, TypeMap};
-use hyper::{Body, Request as HyperRequest, StatusCode};
-use hyper::body::{self, Bytes};
+use hyper::{Body, Request as HyperRequest, StatusCode, Version};
+use hyper::body::{self, Bytes, HttpBody};
+use futures::StreamExt;
 use hyper::header;
+use encoding_rs::Encoding;
+use mime::Mime;
 use serde::Deserialize;
+use serde::de::DeserializeOwned;
 use serde_json;
+use std::error::Error as StdError;
+use std::fmt::Display;
+use std::str::FromStr;
 use std::mem;
-use std::net::SocketAddr;
 use std::sync::Arc;
+use crate::body_limit::BodyTooLarge;
+use crate::endpoint::Endpoint;
+use crate::multipart::{Multipart, MultipartOptions};
 use crate::urlencoded::{self, Params};
 
 /// A container for all the request data.
@@ -26,14 +35,18 @@ pub struct Request<D = ()> {
 
     data: Arc<D>,
 
-    remote_addr: Option<SocketAddr>,
+    remote_addr: Option<Endpoint>,
+
+    secure: bool,
 
     raw_body_cache: Option<Bytes>,
+
+    body_limit: Option<usize>,
 }
 
 impl<D> Request<D> {
     pub fn from_internal(req: HyperRequest<Body>,
-                         remote_addr: Option<SocketAddr>,
+                         remote_addr: Option<Endpoint>,
                          data: Arc<D>) -> Request<D> {
         Request {
             origin: req,
@@ -42,12 +55,89 @@ impl<D> Request<D> {
             map: TypeMap::custom(),
             data: data,
             remote_addr: remote_addr,
-            raw_body_cache: None
+            secure: false,
+            raw_body_cache: None,
+            body_limit: None,
         }
     }
 
+    /// The value of the route parameter `key`, percent-decoded, or `None`
+    /// if there is no such parameter or no route matched the request.
     pub fn param(&self, key: &str) -> Option<&str> {
-        self.route_result.as_ref().unwrap().param(key)
+        self.route_result.as_ref().and_then(|route| route.param(key))
+    }
+
+    /// The route parameter `key` parsed as a `T`.
+    ///
+    /// Fails with `400 Bad Request` if the value doesn't parse, and with
+    /// `500 Internal Server Error` if the route has no such parameter.
+    ///
+    /// # Examples
+    /// ```rust
+    /// #[macro_use] extern crate nickel;
+    /// use nickel::{Nickel, HttpRouter, Request, Response, MiddlewareResult};
+    ///
+    /// fn show_invoice(req: &mut Request, res: Response) -> MiddlewareResult {
+    ///     let id = try_with!(res, req.param_as::<u64>("id"));
+    ///     res.send(format!("Invoice {}", id))
+    /// }
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///     server.get("/invoices/:id", show_invoice);
+    /// }
+    /// ```
+    pub fn param_as<T>(&self, key: &str) -> Result<T, (StatusCode, String)>
+            where T: FromStr, T::Err: Display {
+        let value = self.param(key).ok_or_else(|| {
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("No route parameter named '{}'", key))
+        })?;
+        value.parse().map_err(|e| {
+            (StatusCode::BAD_REQUEST, format!("Invalid route parameter '{}': {}", key, e))
+        })
+    }
+
+    /// The query string deserialized into `T`, with a `400 Bad Request`
+    /// naming the offending field if it doesn't fit.
+    ///
+    /// A key given more than once deserializes into a `Vec`, and keys in
+    /// bracket notation, such as `filter[status]=open`, into a nested struct
+    /// or map. Missing or empty values are `None` for `Option` fields, use
+    /// `#[serde(default)]` for a `Vec` that may be missing.
+    ///
+    /// # Examples
+    /// ```rust
+    /// #[macro_use] extern crate nickel;
+    /// #[macro_use] extern crate serde_derive;
+    /// use nickel::{Nickel, HttpRouter, Request, Response, MiddlewareResult};
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Filter { status: Option<String> }
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Search {
+    ///     page: Option<u32>,
+    ///     #[serde(default)]
+    ///     tag: Vec<String>,
+    ///     filter: Option<Filter>,
+    /// }
+    ///
+    /// // GET /issues?page=2&tag=bug&tag=ui&filter[status]=open
+    /// fn search(req: &mut Request, res: Response) -> MiddlewareResult {
+    ///     let search = try_with!(res, req.query_as::<Search>());
+    ///     let status = search.filter.and_then(|f| f.status);
+    ///     res.send(format!("page {} of {:?} issues tagged {:?}",
+    ///                      search.page.unwrap_or(1), status, search.tag))
+    /// }
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///     server.get("/issues", search);
+    /// }
+    /// ```
+    pub fn query_as<T: DeserializeOwned>(&self) -> Result<T, (StatusCode, String)> {
+        urlencoded::from_params(&urlencoded::parse_uri(self.origin.uri()))
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid query string: {}", e)))
     }
 
     pub fn path_without_query(&self) -> &str {
@@ -58,10 +148,37 @@ impl<D> Request<D> {
         self.data.clone()
     }
 
-    pub fn remote_addr(&self) -> Option<&SocketAddr> {
+    /// The address of the connected client. For Unix domain sockets this is
+    /// usually `Endpoint::Unix(None)` as client sockets are rarely named.
+    pub fn remote_addr(&self) -> Option<&Endpoint> {
         self.remote_addr.as_ref()
     }
 
+    /// The HTTP version the request was made with, e.g. `Version::HTTP_2`
+    /// when HTTP/2 was negotiated.
+    pub fn version(&self) -> Version {
+        self.origin.version()
+    }
+
+    /// Whether the request arrived over a TLS connection.
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    pub(crate) fn set_secure(&mut self, secure: bool) {
+        self.secure = secure;
+    }
+
+    /// The maximum size in bytes of the request body, if any. See
+    /// `Options::max_body_size` and `BodyLimit`.
+    pub fn body_limit(&self) -> Option<usize> {
+        self.body_limit
+    }
+
+    pub(crate) fn set_body_limit(&mut self, limit: Option<usize>) {
+        self.body_limit = limit;
+    }
+
     // (Hopefully) temporary replacements for the Extensible trait. We can't
     // support plugins without Extensible, but access to the ShareMap is used by
     // itself.
@@ -79,11 +196,34 @@ impl<D> Request<D> {
     /// For small non-streaming applications, the body access methods
     /// `raw_body`, `string_body`, `json_as`, and `form_body` are probably more
     /// convenient. Small in this case is hardware dependent, but even small
-    /// modern servers can handle multi-megabyte bodies.
+    /// modern servers can handle multi-megabyte bodies. For forms with file
+    /// uploads, `multipart` reads the body a part at a time.
     ///
     /// `take_body` and the body access method are mutually exclusive. Once one
     /// is called, the other will fail.
+    ///
+    /// If a body size limit is set, the returned body yields an error once
+    /// more than `body_limit` bytes have been read.
     pub fn take_body(&mut self) -> Option<Body> {
+        let body = self.take_raw_body()?;
+        match self.body_limit {
+            Some(limit) => {
+                let mut read = 0;
+                Some(Body::wrap_stream(body.map(move |chunk| {
+                    let chunk = chunk?;
+                    read += chunk.len();
+                    if read > limit {
+                        Err(Box::new(BodyTooLarge { limit }) as Box<dyn StdError + Send + Sync>)
+                    } else {
+                        Ok(chunk)
+                    }
+                })))
+            },
+            None => Some(body),
+        }
+    }
+
+    fn take_raw_body(&mut self) -> Option<Body> {
         if self.body_taken {
             None
         } else {
@@ -110,6 +250,23 @@ impl<D> Request<D> {
 
 // impl<D> Pluggable for Request<D> {}
 
+fn too_large(limit: usize) -> (StatusCode, String) {
+    (StatusCode::PAYLOAD_TOO_LARGE, BodyTooLarge { limit }.to_string())
+}
+
+// Content-Length can be missing or wrong, so count what actually arrives.
+async fn read_limited(mut body: Body, limit: usize) -> Result<Bytes, (StatusCode, String)> {
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        if buf.len() + chunk.len() > limit {
+            return Err(too_large(limit));
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(Bytes::from(buf))
+}
+
 // Various body parsers. These used to live in the body_parser module by
 // implementing a trait on the Request struct. Async and traits are currently
 // kind of cumbersome. We use async_trait for Middleware, because users of
@@ -119,17 +276,35 @@ impl<D> Request<D> {
 impl<D> Request<D> {
     /// Extract the raw body from the request. The body is cached so multiple
     /// middleware may access the body. Note that this may consume a lot of
-    /// memory when large objects are uploaded.
+    /// memory when large objects are uploaded, see `Options::max_body_size`
+    /// to bound it.
     ///
     /// To allow access to the body in different ways, `string_body`, `json_as`
     /// and `form_body` all call this and use the same underlying cache.
+    ///
+    /// Returns `413 Payload Too Large` if the body is larger than
+    /// `body_limit`.
     pub async fn raw_body(&mut self) -> Result<&[u8], (StatusCode, String)> {
         if let None = self.raw_body_cache {
             // read and insert into cache
-            let body = self.take_body().
+            let limit = self.body_limit;
+            if let Some(limit) = limit {
+                // reject up front when the client tells us the size
+                let length = self.origin.headers().get(header::CONTENT_LENGTH)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse::<u64>().ok());
+                if length.is_some_and(|length| length > limit as u64) {
+                    return Err(too_large(limit));
+                }
+            }
+
+            let body = self.take_raw_body().
                 ok_or((StatusCode::INTERNAL_SERVER_ERROR, "body already taken".to_string()))?;
-            let bytes = body::to_bytes::<Body>(body).await.
-                map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            let bytes = match limit {
+                Some(limit) => read_limited(body, limit).await?,
+                None => body::to_bytes::<Body>(body).await.
+                    map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?,
+            };
             self.raw_body_cache = Some(bytes);
         }
         // we've garanteed this unwrap is safe above
@@ -151,14 +326,140 @@ impl<D> Request<D> {
     }
 
     /// Extract the form data from the body.
+    ///
+    /// The content type must be `application/x-www-form-urlencoded`, with
+    /// an optional `charset` parameter, UTF-8 by default. Returns `400 Bad
+    /// Request` for any other content type, and `415 Unsupported Media
+    /// Type` for a charset that isn't supported.
     pub async fn form_body(&mut self) -> Result<Params, (StatusCode, String)> {
-        // check content type
-        match self.origin.headers().get(header::CONTENT_TYPE).map(|v| v.to_str()) {
-            Some(Ok("application/x-www-form-urlencoded")) => {
-                let s = self.string_body().await?;
-                Ok(urlencoded::parse(&s))
-            },
-            _ => Err((StatusCode::BAD_REQUEST, "Wrong Content Type".to_string()))
-        }
+        self.parse_form(StatusCode::BAD_REQUEST).await
+    }
+
+    /// Uses serde to deserialize the form data in the body into type `T`,
+    /// in the same way as `query_as`: a key given more than once
+    /// deserializes into a `Vec`, as returned by `Params::all`, and keys in
+    /// bracket notation into nested structs or maps.
+    ///
+    /// The content type is checked as by `form_body`, except that the wrong
+    /// content type is a `415 Unsupported Media Type`. Values that don't
+    /// fit `T` are a `400 Bad Request` naming the field.
+    ///
+    /// # Examples
+    /// ```rust
+    /// #[macro_use] extern crate nickel;
+    /// #[macro_use] extern crate serde_derive;
+    /// use nickel::{Nickel, HttpRouter, Request, Response, MiddlewareResult};
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Signup {
+    ///     email: String,
+    ///     newsletter: Option<bool>,
+    ///     #[serde(default)]
+    ///     interests: Vec<String>,
+    /// }
+    ///
+    /// async fn signup(req: &mut Request, res: Response) -> MiddlewareResult {
+    ///     let form = try_with!(res, req.form_as::<Signup>().await);
+    ///     let newsletter = if form.newsletter.unwrap_or(false) { "wants" } else { "doesn't want" };
+    ///     res.send(format!("{} is interested in {:?} and {} the newsletter",
+    ///                      form.email, form.interests, newsletter))
+    /// }
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///     server.post("/signup", signup);
+    /// }
+    /// ```
+    pub async fn form_as<T: DeserializeOwned>(&mut self) -> Result<T, (StatusCode, String)> {
+        let params = self.parse_form(StatusCode::UNSUPPORTED_MEDIA_TYPE).await?;
+        urlencoded::from_params(&params)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid form: {}", e)))
+    }
+
+    async fn parse_form(&mut self, wrong_type: StatusCode) -> Result<Params, (StatusCode, String)> {
+        let media_type = self.origin.headers().get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<Mime>().ok())
+            .filter(|mime| mime.essence_str() == mime::APPLICATION_WWW_FORM_URLENCODED.essence_str())
+            .ok_or((wrong_type, "Wrong Content Type".to_string()))?;
+        let encoding = match media_type.get_param(mime::CHARSET) {
+            Some(charset) => Encoding::for_label(charset.as_str().as_bytes())
+                .map(Encoding::output_encoding)
+                .ok_or_else(|| {
+                    (StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("Unsupported charset '{}'", charset))
+                })?,
+            None => encoding_rs::UTF_8,
+        };
+        let body = self.raw_body().await?;
+        Ok(urlencoded::parse_with_encoding(body, encoding))
+    }
+
+    /// Take the body as a `multipart/form-data` form, to read its parts as
+    /// they arrive, with the default `MultipartOptions`, which limit the
+    /// size of each part and of the whole form.
+    ///
+    /// Like `take_body`, this takes the body, so the other body access
+    /// methods fail afterwards, and the `body_limit` applies as the parts
+    /// are read. Returns `415 Unsupported Media Type` for any other content
+    /// type. See the `multipart` module for an example.
+    pub fn multipart(&mut self) -> Result<Multipart, (StatusCode, String)> {
+        self.multipart_with(MultipartOptions::default())
+    }
+
+    /// As `multipart`, with limits on the size of each part and of the whole
+    /// form, and the directory files are spooled to.
+    pub fn multipart_with(&mut self, options: MultipartOptions) -> Result<Multipart, (StatusCode, String)> {
+        let media_type = self.origin.headers().get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<Mime>().ok())
+            .filter(|mime| mime.essence_str() == mime::MULTIPART_FORM_DATA.essence_str())
+            .ok_or((StatusCode::UNSUPPORTED_MEDIA_TYPE, "Wrong Content Type".to_string()))?;
+        let boundary = media_type.get_param(mime::BOUNDARY)
+            .ok_or((StatusCode::BAD_REQUEST, "Missing multipart boundary".to_string()))?
+            .to_string();
+        let body = self.take_body()
+            .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "body already taken".to_string()))?;
+        Ok(Multipart::new(body, boundary, options))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{HttpRouter, MiddlewareResult, Nickel, Request, Response};
+    use crate::test_support::serve;
+    use hyper::StatusCode;
+
+    #[tokio::test]
+    async fn taken_body_is_limited() {
+        let mut req = Request::from_internal(hyper::Request::new(vec![b'a'; 32].into()), None, std::sync::Arc::new(()));
+        req.set_body_limit(Some(16));
+        let body = req.take_body().unwrap();
+        assert!(hyper::body::to_bytes(body).await.is_err());
+    }
+
+    fn show_invoice(req: &mut Request, res: Response) -> MiddlewareResult {
+        let id = try_with!(res, req.param_as::<u64>("id"));
+        let name = req.param("name").unwrap_or("nobody").to_string();
+        res.send(format!("invoice {} for {}", id, name))
+    }
+
+    fn no_params_yet(req: &mut Request, res: Response) -> MiddlewareResult {
+        assert!(req.param("id").is_none());
+        res.next_middleware()
+    }
+
+    #[tokio::test]
+    async fn parses_and_decodes_route_params() {
+        let mut server = Nickel::new();
+        // No route has matched yet, so there are no params
+        server.utilize(no_params_yet);
+        server.get("/invoices/:id", show_invoice);
+        server.get("/invoices/:id/:name", show_invoice);
+        let server = serve(server).await;
+
+        assert_eq!(server.get("/invoices/42").await, (StatusCode::OK, "invoice 42 for nobody".to_string()));
+        assert_eq!(server.get("/invoices/42/John%20Doe").await,
+                   (StatusCode::OK, "invoice 42 for John Doe".to_string()));
+        assert_eq!(server.get("/invoices/abc").await.0, StatusCode::BAD_REQUEST);
     }
 }
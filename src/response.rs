@@ -9,7 +9,7 @@ use std::io;
 use crate::{NickelError, Halt, MiddlewareResult, Responder, Action};
 use crate::template_cache::TemplateCache;
 use modifier::Modifier;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio::fs::File;
 use tokio_util::codec::{BytesCodec, FramedRead};
 use typemap::{ShareMap, TypeMap};
@@ -21,10 +21,13 @@ pub struct Response<D: Send + 'static + Sync = ()> {
     templates: Arc<TemplateCache>,
     data: Arc<D>,
     map: ShareMap,
-    // This should be FnBox, but that's currently unstable
-    //on_send: Vec<Box<dyn FnMut(&mut Response<'a, D>)>>
+    // Shared with the responses made by `fresh`, so the hooks still run if
+    // the handler holding this response never returns it.
+    on_send: Arc<Mutex<Vec<OnSend<D>>>>,
 }
 
+type OnSend<D> = Box<dyn FnOnce(&mut Response<D>) + Send>;
+
 impl<D: Send + 'static + Sync> Response<D> {
     pub fn from_internal(response: HyperResponse<Body>,
                          templates: Arc<TemplateCache>,
@@ -35,10 +38,22 @@ impl<D: Send + 'static + Sync> Response<D> {
             templates: templates,
             data: data,
             map: TypeMap::custom(),
-            //on_send: vec![]
+            on_send: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
+    // A blank response sharing this one's templates, server data and
+    // `on_send` hooks.
+    pub(crate) fn fresh(&self) -> Response<D> {
+        let origin = HyperResponse::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap();
+        let mut res = Response::from_internal(origin, self.templates.clone(), self.data.clone());
+        res.on_send = self.on_send.clone();
+        res
+    }
+
     /// Get a mutable reference to the status.
     pub fn status_mut(&mut self) -> &mut StatusCode {
         self.origin.status_mut()
@@ -232,18 +247,9 @@ impl<D: Send + 'static + Sync> Response<D> {
     // hyper::Response no longer has a start() method. The api has
     // changed a lot, so this may not longer be necessary.
     //
-    // What we are still doing is running the on_send mthods, and
-    // setting fallback headers. Do we need this dedicated method in
-    // the workflow to make sure that happens?
+    // What we are still doing is setting fallback headers. Do we need
+    // this dedicated method in the workflow to make sure that happens?
     pub fn start(&mut self) {
-        // let on_send = mem::replace(&mut self.on_send, vec![]);
-        // for mut f in on_send.into_iter().rev() {
-        //     // TODO: Ensure `f` doesn't call on_send again
-        //     f(self)
-        // }
-
-        // Set fallback headers last after everything runs, if we did this before as an
-        // on_send then it would possibly set redundant things.
         self.set_fallback_headers();
     }
 
@@ -251,10 +257,45 @@ impl<D: Send + 'static + Sync> Response<D> {
         self.data.clone()
     }
 
-    // pub fn on_send<F>(&mut self, f: F)
-    //         where F: FnMut(&mut Response<D>) + 'static {
-    //     self.on_send.push(Box::new(f))
-    // }
+    /// Register a hook to run on the final response, after all the handlers
+    /// and error handlers are done with it and before it is sent. Hooks run
+    /// once each, most recently registered first.
+    ///
+    /// Hooks also run on the response sent in place of this one when a
+    /// handler panics, times out or loses it, but not when it calls `bail`.
+    /// Hooks registered while the hooks are running are ignored.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use nickel::{Nickel, Request, Response, MiddlewareResult};
+    /// use std::time::Instant;
+    ///
+    /// fn timer(_: &mut Request, mut res: Response) -> MiddlewareResult {
+    ///     let started = Instant::now();
+    ///     res.on_send(move |res| {
+    ///         let elapsed = format!("{}ms", started.elapsed().as_millis());
+    ///         res.set_header(nickel::hyper::header::HeaderName::from_static("x-response-time"),
+    ///                        nickel::hyper::header::HeaderValue::from_str(&elapsed).unwrap());
+    ///     });
+    ///     res.next_middleware()
+    /// }
+    ///
+    /// let mut server = Nickel::new();
+    /// server.utilize(timer);
+    /// ```
+    pub fn on_send<F>(&mut self, f: F)
+            where F: FnOnce(&mut Response<D>) + Send + 'static {
+        self.on_send.lock().unwrap().push(Box::new(f))
+    }
+
+    // Run the `on_send` hooks and give up the underlying response.
+    pub(crate) fn finish(mut self) -> HyperResponse<Body> {
+        let on_send = std::mem::take(&mut *self.on_send.lock().unwrap());
+        for f in on_send.into_iter().rev() {
+            f(&mut self)
+        }
+        self.origin
+    }
 
     /// Pass execution off to another Middleware
     ///
@@ -290,7 +331,7 @@ impl<D: Send + 'static + Sync> Response<D> {
     pub fn bail<T>(self, message: T) -> MiddlewareResult<D>
             where T: Into<Cow<'static, str>> {
         let _ = self.end();
-        unsafe { Err(NickelError::without_response(message)) }
+        Err(NickelError::bail(message))
     }
 
     /// Flushes all writing of a response to the client.
@@ -376,3 +417,51 @@ mod modifier_impls {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{HttpRouter, Nickel, Options, Request, Response};
+    use crate::test_support::{fail, lose_response, panics, serve, slow};
+    use hyper::header::{HeaderName, HeaderValue};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn on_send_hooks_run_once_on_every_path() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = runs.clone();
+        let mut server = Nickel::new();
+        server.options = Options::default().request_timeout(Some(Duration::from_millis(100)));
+        server.utilize(move |_: &mut Request, mut res: Response| {
+            let counter = counter.clone();
+            res.on_send(|res| {
+                res.set_header(HeaderName::from_static("x-seen"), HeaderValue::from_static("outer"));
+            });
+            res.on_send(move |res| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                // Runs first, so the outer hook gets the last word
+                res.set_header(HeaderName::from_static("x-seen"), HeaderValue::from_static("inner"));
+                let status = res.status().as_u16().to_string();
+                res.set_header(HeaderName::from_static("x-status"), HeaderValue::from_str(&status).unwrap());
+            });
+            res.next_middleware()
+        });
+        server.get("/ok", middleware!("ok"));
+        server.get("/fail", fail);
+        server.get("/slow", slow);
+        server.get("/panic", panics);
+        server.get("/lost", lose_response);
+        let server = serve(server).await;
+
+        let paths = [("/ok", "200"), ("/fail", "418"), ("/missing", "404"),
+                     ("/slow", "503"), ("/panic", "500"), ("/lost", "500")];
+        for (path, status) in &paths {
+            let res = server.fetch(path).await;
+            assert_eq!(res.headers()["x-seen"], "outer");
+            assert_eq!(res.headers()["x-status"], *status);
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), paths.len());
+    }
+}
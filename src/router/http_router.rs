@@ -1,5 +1,5 @@
 use hyper::Method;
-use crate::middleware::Middleware;
+use crate::middleware::{IntoMiddleware, Middleware};
 use crate::router::Matcher;
 
 pub trait HttpRouter<D: Send + 'static + Sync> {
@@ -121,63 +121,63 @@ pub trait HttpRouter<D: Send + 'static + Sync> {
     ///     server.utilize(router);
     /// }
     /// ```
-    fn get<M: Into<Matcher>, H: Middleware<D>>(&mut self, matcher: M, handler: H) -> &mut Self {
-        self.add_route(Method::GET, matcher, handler)
+    fn get<M: Into<Matcher>, H: IntoMiddleware<D, K>, K>(&mut self, matcher: M, handler: H) -> &mut Self {
+        self.add_route(Method::GET, matcher, handler.into_middleware())
     }
 
     /// Registers a handler to be used for a specific HEAD request.
     ///
     /// Take a look at `get(...)` for a more detailed description.
-    fn head<M: Into<Matcher>, H: Middleware<D>>(&mut self, matcher: M, handler: H) -> &mut Self {
-        self.add_route(Method::HEAD, matcher, handler)
+    fn head<M: Into<Matcher>, H: IntoMiddleware<D, K>, K>(&mut self, matcher: M, handler: H) -> &mut Self {
+        self.add_route(Method::HEAD, matcher, handler.into_middleware())
     }
 
     /// Registers a handler to be used for a specific POST request.
     ///
     /// Take a look at `get(...)` for a more detailed description.
-    fn post<M: Into<Matcher>, H: Middleware<D>>(&mut self, matcher: M, handler: H) -> &mut Self {
-        self.add_route(Method::POST, matcher, handler)
+    fn post<M: Into<Matcher>, H: IntoMiddleware<D, K>, K>(&mut self, matcher: M, handler: H) -> &mut Self {
+        self.add_route(Method::POST, matcher, handler.into_middleware())
     }
 
     /// Registers a handler to be used for a specific PUT request.
     ///
     /// Take a look at `get(...)` for a more detailed description.
-    fn put<M: Into<Matcher>, H: Middleware<D>>(&mut self, matcher: M, handler: H) -> &mut Self {
-        self.add_route(Method::PUT, matcher, handler)
+    fn put<M: Into<Matcher>, H: IntoMiddleware<D, K>, K>(&mut self, matcher: M, handler: H) -> &mut Self {
+        self.add_route(Method::PUT, matcher, handler.into_middleware())
     }
 
     /// Registers a handler to be used for a specific DELETE request.
     ///
     /// Take a look at `get(...)` for a more detailed description.
-    fn delete<M: Into<Matcher>, H: Middleware<D>>(&mut self, matcher: M, handler: H) -> &mut Self {
-        self.add_route(Method::DELETE, matcher, handler)
+    fn delete<M: Into<Matcher>, H: IntoMiddleware<D, K>, K>(&mut self, matcher: M, handler: H) -> &mut Self {
+        self.add_route(Method::DELETE, matcher, handler.into_middleware())
     }
 
     /// Registers a handler to be used for a specific CONNECT request.
     ///
     /// Take a look at `get(...)` for a more detailed description.
-    fn connect<M: Into<Matcher>, H: Middleware<D>>(&mut self, matcher: M, handler: H) -> &mut Self {
-        self.add_route(Method::CONNECT, matcher, handler)
+    fn connect<M: Into<Matcher>, H: IntoMiddleware<D, K>, K>(&mut self, matcher: M, handler: H) -> &mut Self {
+        self.add_route(Method::CONNECT, matcher, handler.into_middleware())
     }
 
     /// Registers a handler to be used for a specific OPTIONS request.
     ///
     /// Take a look at `get(...)` for a more detailed description.
-    fn options<M: Into<Matcher>, H: Middleware<D>>(&mut self, matcher: M, handler: H) -> &mut Self {
-        self.add_route(Method::OPTIONS, matcher, handler)
+    fn options<M: Into<Matcher>, H: IntoMiddleware<D, K>, K>(&mut self, matcher: M, handler: H) -> &mut Self {
+        self.add_route(Method::OPTIONS, matcher, handler.into_middleware())
     }
 
     /// Registers a handler to be used for a specific TRACE request.
     ///
     /// Take a look at `get(...)` for a more detailed description.
-    fn trace<M: Into<Matcher>, H: Middleware<D>>(&mut self, matcher: M, handler: H) -> &mut Self {
-        self.add_route(Method::TRACE, matcher, handler)
+    fn trace<M: Into<Matcher>, H: IntoMiddleware<D, K>, K>(&mut self, matcher: M, handler: H) -> &mut Self {
+        self.add_route(Method::TRACE, matcher, handler.into_middleware())
     }
 
     /// Registers a handler to be used for a specific PATCH request.
     ///
     /// Take a look at `get(...)` for a more detailed description.
-    fn patch<M: Into<Matcher>, H: Middleware<D>>(&mut self, matcher: M, handler: H) -> &mut Self {
-        self.add_route(Method::PATCH, matcher, handler)
+    fn patch<M: Into<Matcher>, H: IntoMiddleware<D, K>, K>(&mut self, matcher: M, handler: H) -> &mut Self {
+        self.add_route(Method::PATCH, matcher, handler.into_middleware())
     }
 }
@@ -5,6 +5,7 @@ use crate::request::Request;
 use crate::response::Response;
 use crate::router::HttpRouter;
 use hyper::{Method, StatusCode};
+use percent_encoding::percent_decode_str;
 use crate::router::{Matcher, FORMAT_PARAM};
 
 /// A Route is the basic data structure that stores both the path
@@ -29,6 +30,11 @@ pub struct RouteResult {
 }
 
 impl RouteResult {
+    /// All the route parameters, as pairs of name and value.
+    pub fn params(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(k, v)| (&k[..], &v[..]))
+    }
+
     pub fn param(&self, key: &str) -> Option<&str> {
         for &(ref k, ref v) in &self.params {
             if k == &key {
@@ -84,7 +90,8 @@ fn extract_params<D>(route: &Route<D>, path: &str) -> Vec<(String, String)> {
             } else {
                 return None;
             };
-            Some((name.to_string(), capture.as_str().to_string()))
+            let value = percent_decode_str(capture.as_str()).decode_utf8_lossy();
+            Some((name.to_string(), value.into_owned()))
         })
         .collect()
 }
@@ -224,14 +231,14 @@ fn can_match_var_routes () {
     assert!(route_result.is_some());
 
     let route_result = route_result.unwrap().0;
-    assert_eq!(route_result.param("userid"), Some("John%20Doe"));
+    assert_eq!(route_result.param("userid"), Some("John Doe"));
 
     // check for optional format param
     let route_result = route_store.match_route(&Method::GET, "/foo/John%20Doe.json");
     assert!(route_result.is_some());
 
     let route_result = route_result.unwrap().0;
-    assert_eq!(route_result.param("userid"), Some("John%20Doe"));
+    assert_eq!(route_result.param("userid"), Some("John Doe"));
     assert_eq!(route_result.param("format"), Some("json"));
 
     // ensure format works with queries
@@ -1,73 +1,830 @@
 use std::clone::Clone;
 use std::convert::Infallible;
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::future::Future;
+use std::io;
+use std::net::{self, SocketAddr, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net as unix;
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::task::{Context, Poll};
 use std::time::Duration;
-use hyper::{Body, Request, Response, StatusCode};
+use futures::future;
+use hyper::{Body, Request};
 use hyper::server::Server as HyperServer;
-use hyper::server::conn::AddrStream;
+use hyper::server::accept::Accept;
 use hyper::service::{make_service_fn, service_fn};
-//use hyper::net::SslServer;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{oneshot, watch, OwnedSemaphorePermit};
+use tokio_util::sync::PollSemaphore;
+use tokio::runtime;
+use tokio::time::{sleep, sleep_until, Instant, Sleep};
+#[cfg(feature = "ssl")]
+use tokio_rustls::{Accept as TlsHandshake, TlsAcceptor};
+#[cfg(feature = "ssl")]
+use tokio_rustls::server::TlsStream;
 
+use crate::endpoint::Endpoint;
+use crate::limits::{ConnectionGuard, Limits, ServerStats};
 use crate::middleware::MiddlewareStack;
-use crate::request;
-use crate::response;
-use crate::template_cache::{ReloadPolicy, TemplateCache};
+use crate::nickel::Options;
+use crate::service::{BoxError, NickelService};
+#[cfg(feature = "tower")]
+use crate::service::{self, ServiceLayer};
+#[cfg(feature = "tower")]
+use tower::ServiceExt;
+use crate::template_cache::TemplateCache;
+#[cfg(feature = "ssl")]
+use crate::tls::TlsConfig;
 
 pub struct Server<D: Send + 'static + Sync> {
     middleware_stack: Arc<MiddlewareStack<D>>,
     templates: Arc<TemplateCache>,
     shared_data: Arc<D>,
+    options: Options,
+    stats: ServerStats,
+    #[cfg(feature = "tower")]
+    layer: Option<ServiceLayer<D>>,
 }
 
 impl<D: Sync + Send + 'static> Server<D> {
-    pub fn new(middleware_stack: MiddlewareStack<D>, reload_policy: ReloadPolicy, data: D) -> Server<D> {
+    pub fn new(mut middleware_stack: MiddlewareStack<D>, data: D, mut options: Options, stats: ServerStats) -> Server<D> {
+        middleware_stack.set_request_timeout(options.request_timeout);
+        middleware_stack.set_last_resort(options.last_resort.take());
+        middleware_stack.set_catch_panics(options.catch_panics);
         Server {
             middleware_stack: Arc::new(middleware_stack),
-            templates: Arc::new(TemplateCache::with_policy(reload_policy)),
-            shared_data: Arc::new(data)
+            templates: Arc::new(TemplateCache::with_policy(options.reload_policy)),
+            shared_data: Arc::new(data),
+            options,
+            stats,
+            #[cfg(feature = "tower")]
+            layer: None,
         }
     }
 
-    pub async fn serve<A: ToSocketAddrs>(self,
-                                         addr: A,
-                                         keep_alive_timeout: Option<Duration>, // TODO: migration cleanup - use this
-                                         thread_count: Option<usize>) // TODO: migration cleanup - use or remove this
-                                         -> Result<(), Box<dyn std::error::Error>> {
-        let socket_addr: SocketAddr = addr.to_socket_addrs()?.next().ok_or(ServerError("bad address".to_string()))?;
+    /// Handle requests with a `NickelService` instead of listening, for
+    /// example to run inside another hyper or tower based server.
+    #[cfg(feature = "tower")]
+    pub fn into_service(self) -> NickelService<D> {
+        NickelService::new(self.middleware_stack, self.templates, self.shared_data, self.options.max_body_size)
+    }
+
+    /// Wrap the application in a tower `Layer`, applied to each connection.
+    /// Combine several layers into one with `tower::ServiceBuilder`.
+    ///
+    /// Requests turned away by `max_connections` or
+    /// `max_in_flight_requests` don't reach the layer, and a layer that
+    /// fails a request drops its connection.
+    #[cfg(feature = "tower")]
+    pub fn layer<L, B>(mut self, layer: L) -> Self
+    where L: tower::Layer<NickelService<D>> + Send + Sync + 'static,
+          L::Service: tower::Service<Request<Body>, Response = hyper::Response<B>> + Clone + Send + 'static,
+          <L::Service as tower::Service<Request<Body>>>::Error: Into<BoxError>,
+          <L::Service as tower::Service<Request<Body>>>::Future: Send + 'static,
+          B: hyper::body::HttpBody + Send + 'static,
+          B::Data: Send,
+          B::Error: Into<BoxError>,
+    {
+        self.layer = Some(service::service_layer(layer));
+        self
+    }
+
+    #[cfg(feature = "tower")]
+    pub(crate) fn set_layer(&mut self, layer: Option<ServiceLayer<D>>) {
+        self.layer = layer;
+    }
+
+    /// Bind to `addr` and serve until the server fails.
+    pub async fn serve<A: ToSocketAddrs>(self, addr: A) -> Result<(), Box<dyn std::error::Error>> {
+        self.start(addr).await?.wait().await
+    }
+
+    /// Bind to `addr` and serve connections in the background.
+    pub async fn start<A: ToSocketAddrs>(self, addr: A) -> Result<ListeningServer, Box<dyn std::error::Error>> {
+        let listener = Listener::bind(addr)?;
+        self.start_listener(listener)
+    }
+
+    /// Bind to `addr` and serve HTTPS connections in the background,
+    /// terminating TLS with the given configuration.
+    #[cfg(feature = "ssl")]
+    pub async fn start_https<A: ToSocketAddrs>(self, addr: A, tls: TlsConfig)
+                                               -> Result<ListeningServer, Box<dyn std::error::Error>> {
+        let listener = Listener::bind(addr)?.with_tls(tls.acceptor(self.options.http2)?);
+        self.start_listener(listener)
+    }
+
+    /// Serve connections in the background on an already bound listener.
+    pub async fn start_on(self, listener: net::TcpListener) -> Result<ListeningServer, Box<dyn std::error::Error>> {
+        let listener = Listener::from_std(listener)?;
+        self.start_listener(listener)
+    }
+
+    /// Serve HTTPS connections in the background on an already bound
+    /// listener, terminating TLS with the given configuration.
+    #[cfg(feature = "ssl")]
+    pub async fn start_https_on(self, listener: net::TcpListener, tls: TlsConfig)
+                                -> Result<ListeningServer, Box<dyn std::error::Error>> {
+        let listener = Listener::from_std(listener)?.with_tls(tls.acceptor(self.options.http2)?);
+        self.start_listener(listener)
+    }
 
-        let make_svc = make_service_fn(move |socket: &AddrStream| {
-            let remote_addr = socket.remote_addr();
-            let mw = self.middleware_stack.clone();
-            let data = self.shared_data.clone();
-            let res_templates = self.templates.clone();
+    /// Bind a Unix domain socket at `path` and serve connections in the
+    /// background.
+    ///
+    /// If `Options::remove_stale_socket` is set, a socket file left behind
+    /// by a previous process is removed before binding. The socket file is
+    /// removed again once the server shuts down.
+    #[cfg(unix)]
+    pub async fn start_unix<P: AsRef<Path>>(self, path: P) -> Result<ListeningServer, Box<dyn std::error::Error>> {
+        let listener = Listener::bind_unix(path.as_ref(), self.options.remove_stale_socket)?;
+        self.start_listener(listener)
+    }
+
+    fn start_listener(self, listener: Listener) -> Result<ListeningServer, Box<dyn std::error::Error>> {
+        let scheme = listener.scheme();
+        let local_addr = listener.local_addr()?;
+        let mut options = self.options;
+        let on_listen = options.on_listen.take();
+        let output_on_listen = options.output_on_listen;
+        let thread_count = options.thread_count;
+        let max_body_size = options.max_body_size;
+        let stats = self.stats;
+        let limits = Arc::new(Limits::new(stats.clone(),
+                                          options.overload_policy,
+                                          options.max_connections,
+                                          options.max_in_flight_requests));
+        let conn_limits = limits.clone();
+
+        let app = NickelService::new(self.middleware_stack, self.templates, self.shared_data, max_body_size);
+        #[cfg(feature = "tower")]
+        let layer = self.layer;
+        let make_svc = make_service_fn(move |conn: &Connection| {
+            let app = app.clone()
+                .remote_addr(Some(conn.remote_addr().clone()))
+                .secure(conn.is_secure());
+            #[cfg(feature = "tower")]
+            let layered = layer.as_ref().map(|layer| layer(app.clone()));
+            let active = conn.active_requests();
+            let rejected = conn.is_rejected();
+            let limits = limits.clone();
             async move {
                 Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
-                    let mw2 = mw.clone();
-                    let req_data2 = data.clone();
-                    let res_data2 = data.clone();
-                    let res_templates2 = res_templates.clone();
+                    let active = ActiveRequest::new(&active);
+                    let app = app.clone();
+                    #[cfg(feature = "tower")]
+                    let layered = layered.clone();
+                    let limits = limits.clone();
                     async move {
-                        let res = Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty()).unwrap();
-                        let nickel_req = request::Request::from_internal(req,
-                                                                         Some(remote_addr.to_owned()),
-                                                                         req_data2);
-                        let nickel_res = response::Response::from_internal(res,
-                                                                           res_templates2,
-                                                                           res_data2);
-                        let final_res = mw2.invoke(nickel_req, nickel_res).await;
-                        Ok::<_, Infallible>(final_res)
+                        if rejected {
+                            return Ok(limits.overloaded(true));
+                        }
+                        let _admitted = match limits.admit_request().await {
+                            Some(guard) => guard,
+                            None => return Ok(limits.overloaded(false)),
+                        };
+
+                        #[cfg(feature = "tower")]
+                        if let Some(layered) = layered {
+                            let res = layered.oneshot(req).await;
+                            drop(active);
+                            return res;
+                        }
+
+                        let res = app.handle(req).await;
+                        drop(active);
+                        Ok::<_, BoxError>(res?)
                     }
                 }))
             }
         });
-        let server = HyperServer::bind(&socket_addr).serve(make_svc);
 
-        println!("Listening on http://{}", server.local_addr());
-        
-        server.await?;
-        
-        Ok(())
+        let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
+        let (done_tx, done_rx) = oneshot::channel();
+        let serve = async move {
+            // Sockets and signals register with the runtime that polls them,
+            // so they are only set up once we are running on it.
+            let signal = if options.shutdown_on_signal {
+                Some(ShutdownSignal::new()?)
+            } else {
+                None
+            };
+            let incoming = listener.into_incoming(options.keep_alive_timeout, conn_limits)?;
+            #[cfg(unix)]
+            let socket_path = incoming.unix_path().map(Path::to_path_buf);
+
+            let mut builder = HyperServer::builder(incoming);
+            if let Some(timeout) = options.header_read_timeout {
+                builder = builder.http1_header_read_timeout(timeout);
+            }
+            let result = builder
+                .http1_keepalive(options.keep_alive_timeout.is_some())
+                .http1_only(!options.http2)
+                .http2_max_concurrent_streams(options.http2_max_concurrent_streams)
+                .http2_initial_stream_window_size(options.http2_initial_stream_window_size)
+                .http2_initial_connection_window_size(options.http2_initial_connection_window_size)
+                .http2_adaptive_window(options.http2_adaptive_window)
+                .serve(make_svc)
+                .with_graceful_shutdown(async move {
+                    let requested = async {
+                        // A dropped handle detaches the server rather than stopping it
+                        if shutdown_rx.wait_for(|&stop| stop).await.is_err() {
+                            future::pending::<()>().await;
+                        }
+                    };
+                    match signal {
+                        Some(signal) => tokio::select! {
+                            _ = requested => {},
+                            _ = signal.recv() => info!("Received shutdown signal"),
+                        },
+                        None => requested.await,
+                    }
+                    info!("Shutting down, waiting for in-flight requests");
+                })
+                .await;
+
+            #[cfg(unix)]
+            {
+                if let Some(path) = socket_path {
+                    if let Err(e) = fs::remove_file(&path) {
+                        warn!("failed to remove socket {}: {}", path.display(), e);
+                    }
+                }
+            }
+            Ok(result?)
+        };
+
+        match thread_count {
+            Some(threads) => {
+                // Run on a runtime of our own so the worker count is honoured
+                // regardless of how the caller's runtime is configured.
+                let runtime = runtime::Builder::new_multi_thread()
+                    .worker_threads(threads)
+                    .thread_name("nickel-worker")
+                    .enable_all()
+                    .build()?;
+                thread::Builder::new()
+                    .name("nickel-server".to_string())
+                    .spawn(move || {
+                        let _ = done_tx.send(runtime.block_on(serve));
+                    })?;
+            },
+            None => {
+                tokio::spawn(async move {
+                    let _ = done_tx.send(serve.await);
+                });
+            }
+        }
+
+        if output_on_listen {
+            info!("Listening on {}://{}", scheme, local_addr);
+        }
+        if let Some(on_listen) = on_listen {
+            on_listen(&local_addr);
+        }
+
+        Ok(ListeningServer {
+            local_addr,
+            stats,
+            shutdown: shutdown_tx,
+            done: done_rx,
+        })
+    }
+}
+
+/// A handle to a running server, returned by `Nickel::start`.
+///
+/// Dropping the handle leaves the server running in the background.
+pub struct ListeningServer {
+    local_addr: Endpoint,
+    stats: ServerStats,
+    shutdown: watch::Sender<bool>,
+    done: oneshot::Receiver<Result<(), BoxError>>,
+}
+
+impl ListeningServer {
+    /// The address the server is bound to.
+    pub fn local_addr(&self) -> &Endpoint {
+        &self.local_addr
+    }
+
+    /// Counters for the connections and requests being served.
+    pub fn stats(&self) -> &ServerStats {
+        &self.stats
+    }
+
+    /// Stop accepting new connections and begin a graceful shutdown. Use
+    /// `wait` to know when the in-flight requests have drained.
+    pub fn shutdown(&self) {
+        let _ = self.shutdown.send(true);
+    }
+
+    /// Wait for the server to stop. Following a `shutdown`, this resolves
+    /// once all in-flight requests have been answered and their connections
+    /// closed.
+    pub async fn wait(self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.done.await {
+            Ok(result) => result.map_err(|e| e as Box<dyn std::error::Error>),
+            Err(_) => Err(Box::new(ServerError("server task was cancelled".to_string()))),
+        }
+    }
+}
+
+// Ctrl-C or, on unix, SIGTERM. Registered when the server starts so signals
+// arriving before the first poll are not lost.
+struct ShutdownSignal {
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+}
+
+impl ShutdownSignal {
+    fn new() -> io::Result<ShutdownSignal> {
+        Ok(ShutdownSignal {
+            #[cfg(unix)]
+            terminate: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?,
+        })
+    }
+
+    #[cfg(unix)]
+    async fn recv(mut self) {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = self.terminate.recv() => {},
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn recv(self) {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+
+enum Socket {
+    Tcp(net::TcpListener),
+    #[cfg(unix)]
+    Unix(unix::UnixListener, PathBuf),
+}
+
+/// A bound socket that has not yet been handed to a runtime.
+struct Listener {
+    socket: Socket,
+    #[cfg(feature = "ssl")]
+    tls: Option<TlsAcceptor>,
+}
+
+impl Listener {
+    fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Listener> {
+        let socket_addr: SocketAddr = addr.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, ServerError("bad address".to_string())))?;
+        Listener::from_std(net::TcpListener::bind(socket_addr)?)
+    }
+
+    fn from_std(listener: net::TcpListener) -> io::Result<Listener> {
+        listener.set_nonblocking(true)?;
+        Ok(Listener::new(Socket::Tcp(listener)))
+    }
+
+    #[cfg(unix)]
+    fn bind_unix(path: &Path, remove_stale: bool) -> io::Result<Listener> {
+        if remove_stale {
+            remove_stale_socket(path)?;
+        }
+        let listener = unix::UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Listener::new(Socket::Unix(listener, path.to_path_buf())))
+    }
+
+    fn new(socket: Socket) -> Listener {
+        Listener {
+            socket,
+            #[cfg(feature = "ssl")]
+            tls: None,
+        }
+    }
+
+    #[cfg(feature = "ssl")]
+    fn with_tls(mut self, acceptor: TlsAcceptor) -> Listener {
+        self.tls = Some(acceptor);
+        self
+    }
+
+    fn local_addr(&self) -> io::Result<Endpoint> {
+        match self.socket {
+            Socket::Tcp(ref listener) => listener.local_addr().map(Endpoint::Tcp),
+            #[cfg(unix)]
+            Socket::Unix(_, ref path) => Ok(Endpoint::Unix(Some(path.clone()))),
+        }
+    }
+
+    fn scheme(&self) -> &'static str {
+        #[cfg(feature = "ssl")]
+        {
+            if self.tls.is_some() {
+                return "https";
+            }
+        }
+        "http"
+    }
+
+    // Must be called from within the runtime that will serve the connections.
+    fn into_incoming(self, idle_timeout: Option<Duration>, limits: Arc<Limits>) -> io::Result<Incoming> {
+        let listener = match self.socket {
+            Socket::Tcp(listener) => AcceptSocket::Tcp(TcpListener::from_std(listener)?),
+            #[cfg(unix)]
+            Socket::Unix(listener, path) => AcceptSocket::Unix(UnixListener::from_std(listener)?, path),
+        };
+        Ok(Incoming {
+            listener,
+            #[cfg(feature = "ssl")]
+            tls: self.tls,
+            idle_timeout,
+            connection_permits: limits.queued_connections().map(PollSemaphore::new),
+            permit: None,
+            limits,
+            timeout: None,
+        })
+    }
+}
+
+// Only remove the file if it is a socket nobody is listening on any more, so
+// a typo in the path can't delete an unrelated file or steal a live socket.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            match unix::UnixStream::connect(path) {
+                Ok(_) => Err(io::Error::new(io::ErrorKind::AddrInUse,
+                                            format!("{} is in use by another process", path.display()))),
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                    info!("Removing stale socket {}", path.display());
+                    fs::remove_file(path)
+                },
+                Err(e) => Err(e),
+            }
+        },
+        _ => Ok(()),
+    }
+}
+
+enum AcceptSocket {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+/// Accepts connections from a listening socket, optionally wrapping them in
+/// TLS, and hands them to hyper.
+struct Incoming {
+    listener: AcceptSocket,
+    #[cfg(feature = "ssl")]
+    tls: Option<TlsAcceptor>,
+    idle_timeout: Option<Duration>,
+    limits: Arc<Limits>,
+    // Set when connections wait for room under `max_connections`, the
+    // permit is taken before accepting so excess clients stay in the backlog.
+    connection_permits: Option<PollSemaphore>,
+    permit: Option<OwnedSemaphorePermit>,
+    // Backoff after an accept error that wasn't caused by a single connection
+    // (e.g. running out of file descriptors).
+    timeout: Option<Pin<Box<Sleep>>>,
+}
+
+impl Incoming {
+    #[cfg(unix)]
+    fn unix_path(&self) -> Option<&Path> {
+        match self.listener {
+            AcceptSocket::Unix(_, ref path) => Some(path),
+            _ => None,
+        }
+    }
+
+    fn poll_accept_socket(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<(Stream, Endpoint)>> {
+        match self.listener {
+            AcceptSocket::Tcp(ref listener) => {
+                listener.poll_accept(cx).map_ok(|(stream, addr)| {
+                    #[cfg(feature = "ssl")]
+                    let stream = match self.tls {
+                        Some(ref tls) => Stream::TlsHandshake(Box::new(tls.accept(stream))),
+                        None => Stream::Tcp(stream),
+                    };
+                    #[cfg(not(feature = "ssl"))]
+                    let stream = Stream::Tcp(stream);
+                    (stream, Endpoint::Tcp(addr))
+                })
+            },
+            #[cfg(unix)]
+            AcceptSocket::Unix(ref listener, _) => {
+                listener.poll_accept(cx).map_ok(|(stream, addr)| {
+                    let path = addr.as_pathname().map(Path::to_path_buf);
+                    (Stream::Unix(stream), Endpoint::Unix(path))
+                })
+            },
+        }
+    }
+}
+
+impl Accept for Incoming {
+    type Conn = Connection;
+    type Error = io::Error;
+
+    fn poll_accept(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<io::Result<Connection>>> {
+        if let Some(ref mut timeout) = self.timeout {
+            match timeout.as_mut().poll(cx) {
+                Poll::Ready(()) => self.timeout = None,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        if self.permit.is_none() {
+            if let Some(ref mut permits) = self.connection_permits {
+                match permits.poll_acquire(cx) {
+                    Poll::Ready(permit) => self.permit = permit,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        }
+
+        loop {
+            match self.poll_accept_socket(cx) {
+                Poll::Ready(Ok((stream, remote_addr))) => {
+                    let permit = self.permit.take();
+                    let admitted = self.limits.admit_connection(permit);
+                    if admitted.is_none() {
+                        debug!("rejecting connection from {}, too many connections", remote_addr);
+                    }
+                    let conn = Connection {
+                        stream,
+                        remote_addr,
+                        idle: self.idle_timeout.map(IdleTimeout::new),
+                        admitted,
+                    };
+                    return Poll::Ready(Some(Ok(conn)));
+                },
+                Poll::Ready(Err(ref e)) if is_connection_error(e) => {
+                    debug!("accepted connection already errored: {}", e);
+                },
+                Poll::Ready(Err(e)) => {
+                    // Returning the error would stop hyper's accept loop, so
+                    // log it and back off for a second instead.
+                    error!("accept error: {}", e);
+                    let mut timeout = Box::pin(sleep(Duration::from_secs(1)));
+                    match timeout.as_mut().poll(cx) {
+                        Poll::Ready(()) => continue,
+                        Poll::Pending => {
+                            self.timeout = Some(timeout);
+                            return Poll::Pending;
+                        }
+                    }
+                },
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(e.kind(),
+             io::ErrorKind::ConnectionRefused |
+             io::ErrorKind::ConnectionAborted |
+             io::ErrorKind::ConnectionReset)
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    #[cfg(feature = "ssl")]
+    TlsHandshake(Box<TlsHandshake<TcpStream>>),
+    #[cfg(feature = "ssl")]
+    Tls(Box<TlsStream<TcpStream>>),
+    // The TLS handshake failed, the socket has already been dropped.
+    #[cfg(feature = "ssl")]
+    TlsFailed,
+}
+
+/// A single accepted client connection.
+///
+/// TLS handshakes are driven lazily by the first read or write so a slow
+/// client can't hold up the accept loop.
+pub(crate) struct Connection {
+    stream: Stream,
+    remote_addr: Endpoint,
+    idle: Option<IdleTimeout>,
+    // `None` if the server was at `max_connections`
+    admitted: Option<ConnectionGuard>,
+}
+
+/// Closes a connection once it has seen no traffic and had no request in
+/// progress for the configured duration.
+struct IdleTimeout {
+    duration: Duration,
+    last_activity: Instant,
+    timer: Pin<Box<Sleep>>,
+    active: Arc<AtomicUsize>,
+}
+
+impl IdleTimeout {
+    fn new(duration: Duration) -> IdleTimeout {
+        let now = Instant::now();
+        IdleTimeout {
+            duration,
+            last_activity: now,
+            timer: Box::pin(sleep_until(now + duration)),
+            active: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn poll_expired(&mut self, cx: &mut Context<'_>) -> bool {
+        if self.active.load(Ordering::Acquire) > 0 {
+            return false;
+        }
+        let deadline = self.last_activity + self.duration;
+        if self.timer.deadline() != deadline {
+            self.timer.as_mut().reset(deadline);
+        }
+        self.timer.as_mut().poll(cx).is_ready()
+    }
+}
+
+/// Marks a request as in progress on its connection for as long as it lives.
+struct ActiveRequest(Option<Arc<AtomicUsize>>);
+
+impl ActiveRequest {
+    fn new(active: &Option<Arc<AtomicUsize>>) -> ActiveRequest {
+        if let Some(ref count) = *active {
+            count.fetch_add(1, Ordering::AcqRel);
+        }
+        ActiveRequest(active.clone())
+    }
+}
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        if let Some(ref count) = self.0 {
+            count.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+impl Connection {
+    pub(crate) fn remote_addr(&self) -> &Endpoint {
+        &self.remote_addr
+    }
+
+    fn is_rejected(&self) -> bool {
+        self.admitted.is_none()
+    }
+
+    fn active_requests(&self) -> Option<Arc<AtomicUsize>> {
+        self.idle.as_ref().map(|idle| idle.active.clone())
+    }
+
+    fn touch(&mut self) {
+        if let Some(ref mut idle) = self.idle {
+            idle.last_activity = Instant::now();
+        }
+    }
+
+    pub(crate) fn is_secure(&self) -> bool {
+        match self.stream {
+            Stream::Tcp(_) => false,
+            #[cfg(unix)]
+            Stream::Unix(_) => false,
+            #[cfg(feature = "ssl")]
+            Stream::TlsHandshake(_) | Stream::Tls(_) | Stream::TlsFailed => true,
+        }
+    }
+
+    #[cfg(feature = "ssl")]
+    fn poll_handshake(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.stream {
+            Stream::TlsHandshake(ref mut accept) => {
+                match Pin::new(&mut **accept).poll(cx) {
+                    Poll::Ready(Ok(tls)) => self.stream = Stream::Tls(Box::new(tls)),
+                    Poll::Ready(Err(e)) => {
+                        debug!("TLS handshake with {} failed: {}", self.remote_addr, e);
+                        self.stream = Stream::TlsFailed;
+                        return Poll::Ready(Err(e));
+                    },
+                    Poll::Pending => return Poll::Pending,
+                }
+            },
+            Stream::TlsFailed => {
+                return Poll::Ready(Err(io::Error::new(io::ErrorKind::NotConnected, "TLS handshake failed")));
+            },
+            _ => {}
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    #[cfg(not(feature = "ssl"))]
+    fn poll_handshake(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+macro_rules! ready_handshake {
+    ($conn:expr, $cx:expr) => {
+        match $conn.poll_handshake($cx) {
+            Poll::Ready(Ok(())) => {},
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        }
+    };
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready_handshake!(this, cx);
+        let filled = buf.filled().len();
+        let poll = match this.stream {
+            Stream::Tcp(ref mut s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(ref mut s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(feature = "ssl")]
+            Stream::Tls(ref mut s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(feature = "ssl")]
+            Stream::TlsHandshake(_) | Stream::TlsFailed => unreachable!(),
+        };
+        match poll {
+            Poll::Ready(Ok(())) if buf.filled().len() > filled => this.touch(),
+            Poll::Pending => {
+                if let Some(ref mut idle) = this.idle {
+                    if idle.poll_expired(cx) {
+                        debug!("closing idle connection from {}", this.remote_addr);
+                        // Report EOF, which hyper treats as the client going away
+                        return Poll::Ready(Ok(()));
+                    }
+                }
+            },
+            _ => {}
+        }
+        poll
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready_handshake!(this, cx);
+        let poll = match this.stream {
+            Stream::Tcp(ref mut s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(ref mut s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(feature = "ssl")]
+            Stream::Tls(ref mut s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(feature = "ssl")]
+            Stream::TlsHandshake(_) | Stream::TlsFailed => unreachable!(),
+        };
+        if let Poll::Ready(Ok(n)) = poll {
+            if n > 0 {
+                this.touch();
+            }
+        }
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready_handshake!(this, cx);
+        match this.stream {
+            Stream::Tcp(ref mut s) => Pin::new(s).poll_flush(cx),
+            #[cfg(unix)]
+            Stream::Unix(ref mut s) => Pin::new(s).poll_flush(cx),
+            #[cfg(feature = "ssl")]
+            Stream::Tls(ref mut s) => Pin::new(s).poll_flush(cx),
+            #[cfg(feature = "ssl")]
+            Stream::TlsHandshake(_) | Stream::TlsFailed => unreachable!(),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready_handshake!(this, cx);
+        match this.stream {
+            Stream::Tcp(ref mut s) => Pin::new(s).poll_shutdown(cx),
+            #[cfg(unix)]
+            Stream::Unix(ref mut s) => Pin::new(s).poll_shutdown(cx),
+            #[cfg(feature = "ssl")]
+            Stream::Tls(ref mut s) => Pin::new(s).poll_shutdown(cx),
+            #[cfg(feature = "ssl")]
+            Stream::TlsHandshake(_) | Stream::TlsFailed => unreachable!(),
+        }
     }
 }
 
@@ -81,3 +838,215 @@ impl std::fmt::Display for ServerError {
         write!(f, "ServerError: {}", self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{HttpRouter, Nickel, Options};
+    use crate::test_support::{closed_within, get_raw, read, serve, slow};
+    use hyper::{Client, StatusCode};
+    use std::thread;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn can_get_server_address_with_random_port() {
+        let listening = Nickel::new().start("127.0.0.1:0").await.unwrap();
+
+        let addr = listening.local_addr().tcp().unwrap();
+        assert_eq!(addr.ip().to_string(), "127.0.0.1");
+        assert_ne!(addr.port(), 0);
+
+        listening.shutdown();
+        listening.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn shutdown_drains_in_flight_requests() {
+        let mut server = Nickel::new();
+        server.get("/slow", slow);
+        let listening = server.start("127.0.0.1:0").await.unwrap();
+        let url: hyper::Uri = format!("http://{}/slow", listening.local_addr()).parse().unwrap();
+
+        let in_flight = tokio::spawn(Client::new().get(url.clone()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        listening.shutdown();
+        listening.wait().await.unwrap();
+
+        let res = in_flight.await.unwrap().unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"done");
+
+        // the listener is gone, new connections are refused
+        assert!(Client::new().get(url).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn idle_keep_alive_connections_are_closed() {
+        let mut server = Nickel::new();
+        server.keep_alive_timeout(Some(Duration::from_millis(200)));
+        server.get("/", middleware!("hi"));
+        let server = serve(server).await;
+
+        let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+        let res = get_raw(&mut stream, "/", true).await;
+        assert!(res.starts_with("HTTP/1.1 200"));
+        assert!(!closed_within(&mut stream, Duration::from_millis(50)).await);
+        assert!(closed_within(&mut stream, Duration::from_secs(2)).await);
+    }
+
+    #[tokio::test]
+    async fn keep_alive_timeout_does_not_cut_slow_requests() {
+        let mut server = Nickel::new();
+        server.keep_alive_timeout(Some(Duration::from_millis(100)));
+        server.get("/slow", slow);
+        let server = serve(server).await;
+
+        let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+        let res = get_raw(&mut stream, "/slow", true).await;
+        assert!(res.starts_with("HTTP/1.1 200"));
+        assert!(res.ends_with("done"));
+    }
+
+    #[tokio::test]
+    async fn disabled_keep_alive_closes_after_response() {
+        let mut server = Nickel::new();
+        server.keep_alive_timeout(None);
+        server.get("/", middleware!("hi"));
+        let server = serve(server).await;
+
+        let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+        let res = get_raw(&mut stream, "/", true).await;
+        assert!(res.starts_with("HTTP/1.1 200"));
+        assert!(closed_within(&mut stream, Duration::from_millis(500)).await);
+    }
+
+    #[tokio::test]
+    async fn thread_count_uses_dedicated_workers() {
+        let mut server = Nickel::new();
+        server.options = Options::default().thread_count(Some(2));
+        server.get("/", middleware! {
+            thread::current().name().unwrap_or("").to_string()
+        });
+        let server = serve(server).await;
+
+        let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+        let res = get_raw(&mut stream, "/", false).await;
+        assert!(res.ends_with("nickel-worker"), "{}", res);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_over_unix_socket() {
+        use crate::Endpoint;
+        use std::os::unix::net::UnixListener;
+        use tokio::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("nickel-test-{}.sock", std::process::id()));
+        // leave a stale socket behind, as a crashed process would
+        drop(UnixListener::bind(&path).unwrap());
+
+        let mut server = Nickel::new();
+        server.options = Options::default().remove_stale_socket(true);
+        server.get("/", middleware! { |req|
+            match req.remote_addr() {
+                Some(&Endpoint::Unix(_)) => "unix",
+                _ => "other",
+            }
+        });
+        let listening = server.start_unix(&path).await.unwrap();
+        assert_eq!(listening.local_addr().unix_path(), Some(path.as_path()));
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        let res = get_raw(&mut stream, "/", false).await;
+        assert!(res.starts_with("HTTP/1.1 200"));
+        assert!(res.ends_with("unix"));
+
+        listening.shutdown();
+        listening.wait().await.unwrap();
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn refuses_to_remove_live_socket() {
+        let path = std::env::temp_dir().join(format!("nickel-test-live-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let listening = Nickel::new().start_unix(&path).await.unwrap();
+
+        let mut server = Nickel::new();
+        server.options = Options::default().remove_stale_socket(true);
+        assert!(server.start_unix(&path).await.is_err());
+
+        listening.shutdown();
+        listening.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn serves_on_prebound_listener() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut server = Nickel::new();
+        server.get("/", middleware!("hi"));
+        let listening = server.start_on(listener).await.unwrap();
+        assert_eq!(listening.local_addr().tcp(), Some(addr));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let res = get_raw(&mut stream, "/", false).await;
+        assert!(res.ends_with("hi"));
+
+        listening.shutdown();
+        listening.wait().await.unwrap();
+    }
+
+    async fn h2c_get(url: &str) -> hyper::Result<hyper::Response<hyper::Body>> {
+        let client = Client::builder().http2_only(true).build_http::<hyper::Body>();
+        client.get(url.parse().unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn serves_h2c_when_enabled() {
+        let mut server = Nickel::new();
+        server.options = Options::default()
+            .http2(true)
+            .http2_max_concurrent_streams(Some(16));
+        server.get("/", middleware! { |req|
+            format!("{:?}", req.version())
+        });
+        let server = serve(server).await;
+
+        let res = h2c_get(&server.url("/")).await.unwrap();
+        assert_eq!(res.version(), hyper::Version::HTTP_2);
+        assert_eq!(read(res).await.1, "HTTP/2.0");
+
+        // HTTP/1 clients are still served
+        assert_eq!(server.get("/").await.1, "HTTP/1.1");
+    }
+
+    #[tokio::test]
+    async fn rejects_h2c_by_default() {
+        let mut server = Nickel::new();
+        server.get("/", middleware!("hi"));
+        let server = serve(server).await;
+
+        assert!(h2c_get(&server.url("/")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn slow_headers_are_cut_off() {
+        let mut server = Nickel::new();
+        server.options = Options::default().header_read_timeout(Some(Duration::from_millis(200)));
+        server.get("/", middleware!("hi"));
+        let server = serve(server).await;
+
+        let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n").await.unwrap();
+        let mut buf = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(2), stream.read_to_end(&mut buf)).await;
+        assert!(read.is_ok(), "connection was not closed");
+        assert!(!String::from_utf8_lossy(&buf).contains("hi"));
+    }
+}
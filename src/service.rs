@@ -0,0 +1,202 @@
+//! Handling requests outside of nickel's own server, as a tower `Service`,
+//! and wrapping nickel's server in tower `Layer`s.
+use std::sync::Arc;
+use hyper::{Body, Request as HyperRequest, Response as HyperResponse, StatusCode};
+#[cfg(feature = "tower")]
+use std::future::Future;
+#[cfg(feature = "tower")]
+use std::pin::Pin;
+#[cfg(feature = "tower")]
+use std::task::{Context, Poll};
+#[cfg(feature = "tower")]
+use hyper::body::{Buf, HttpBody};
+#[cfg(feature = "tower")]
+use tower::{Layer, Service, ServiceExt};
+#[cfg(feature = "tower")]
+use tower::util::BoxCloneService;
+
+use crate::endpoint::Endpoint;
+use crate::middleware::{Aborted, MiddlewareStack};
+use crate::request::Request;
+use crate::response::Response;
+use crate::template_cache::TemplateCache;
+
+pub(crate) type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A nickel application that handles one `hyper::Request` at a time,
+/// created with `Nickel::into_service`.
+///
+/// With the `tower` feature this is a `tower::Service`, for embedding a
+/// nickel app in another hyper or tower based server. Requests are handled
+/// as by `Nickel::listen`, except that connection level options such as
+/// `max_connections` are left to the surrounding server.
+pub struct NickelService<D: Send + 'static + Sync = ()> {
+    middleware_stack: Arc<MiddlewareStack<D>>,
+    templates: Arc<TemplateCache>,
+    data: Arc<D>,
+    max_body_size: Option<usize>,
+    remote_addr: Option<Endpoint>,
+    secure: bool,
+}
+
+impl<D: Send + 'static + Sync> Clone for NickelService<D> {
+    fn clone(&self) -> Self {
+        NickelService {
+            middleware_stack: self.middleware_stack.clone(),
+            templates: self.templates.clone(),
+            data: self.data.clone(),
+            max_body_size: self.max_body_size,
+            remote_addr: self.remote_addr.clone(),
+            secure: self.secure,
+        }
+    }
+}
+
+impl<D: Send + 'static + Sync> NickelService<D> {
+    pub(crate) fn new(middleware_stack: Arc<MiddlewareStack<D>>,
+                      templates: Arc<TemplateCache>,
+                      data: Arc<D>,
+                      max_body_size: Option<usize>) -> NickelService<D> {
+        NickelService {
+            middleware_stack,
+            templates,
+            data,
+            max_body_size,
+            remote_addr: None,
+            secure: false,
+        }
+    }
+
+    /// The address of the client on the connection this service handles,
+    /// for `Request::remote_addr`.
+    pub fn remote_addr(mut self, addr: Option<Endpoint>) -> Self {
+        self.remote_addr = addr;
+        self
+    }
+
+    /// Whether the connection this service handles is encrypted, for
+    /// `Request::is_secure`.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Run a request through the middleware stack.
+    pub(crate) async fn handle(&self, req: HyperRequest<Body>) -> Result<HyperResponse<Body>, Aborted> {
+        let res = HyperResponse::builder().status(StatusCode::NOT_FOUND).body(Body::empty()).unwrap();
+        let mut req = Request::from_internal(req, self.remote_addr.clone(), self.data.clone());
+        req.set_secure(self.secure);
+        req.set_body_limit(self.max_body_size);
+        let res = Response::from_internal(res, self.templates.clone(), self.data.clone());
+        self.middleware_stack.invoke(req, res).await
+    }
+}
+
+#[cfg(feature = "tower")]
+impl<D: Send + 'static + Sync> Service<HyperRequest<Body>> for NickelService<D> {
+    type Response = HyperResponse<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<HyperResponse<Body>, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: HyperRequest<Body>) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move { Ok(service.handle(req).await?) })
+    }
+}
+
+/// A tower `Layer` applied to the `NickelService` for each connection.
+#[cfg(feature = "tower")]
+pub(crate) type ServiceLayer<D> = Arc<dyn Fn(NickelService<D>) -> LayeredService + Send + Sync>;
+
+#[cfg(feature = "tower")]
+pub(crate) type LayeredService = BoxCloneService<HyperRequest<Body>, HyperResponse<Body>, BoxError>;
+
+#[cfg(feature = "tower")]
+pub(crate) fn service_layer<D, L, B>(layer: L) -> ServiceLayer<D>
+where D: Send + 'static + Sync,
+      L: Layer<NickelService<D>> + Send + Sync + 'static,
+      L::Service: Service<HyperRequest<Body>, Response = HyperResponse<B>> + Clone + Send + 'static,
+      <L::Service as Service<HyperRequest<Body>>>::Error: Into<BoxError>,
+      <L::Service as Service<HyperRequest<Body>>>::Future: Send + 'static,
+      B: HttpBody + Send + 'static,
+      B::Data: Send,
+      B::Error: Into<BoxError>,
+{
+    Arc::new(move |service| {
+        BoxCloneService::new(layer.layer(service)
+                             .map_response(into_hyper_body)
+                             .map_err(Into::into))
+    })
+}
+
+// Layers such as compression change the body type, hyper's `Body` is what
+// the server sends.
+#[cfg(feature = "tower")]
+fn into_hyper_body<B>(res: HyperResponse<B>) -> HyperResponse<Body>
+where B: HttpBody + Send + 'static,
+      B::Data: Send,
+      B::Error: Into<BoxError>,
+{
+    res.map(|body| {
+        let chunks = futures::stream::unfold(Box::pin(body), |mut body| async move {
+            let chunk = body.data().await?;
+            Some((chunk.map(|mut data| data.copy_to_bytes(data.remaining())), body))
+        });
+        Body::wrap_stream(chunks)
+    })
+}
+
+#[cfg(all(test, feature = "tower"))]
+mod tests {
+    use crate::{HttpRouter, Nickel};
+    use crate::test_support::{read, serve};
+    use hyper::StatusCode;
+
+    #[tokio::test]
+    async fn runs_as_a_tower_service() {
+        use std::net::SocketAddr;
+        use tower::ServiceExt;
+
+        let mut server = Nickel::new();
+        server.get("/hello", middleware! { |req|
+            format!("hello {}", req.remote_addr().unwrap())
+        });
+        let client: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+        let service = server.into_service().remote_addr(Some(client.into()));
+
+        let req = hyper::Request::get("/hello").body(hyper::Body::empty()).unwrap();
+        let res = service.clone().oneshot(req).await.unwrap();
+        assert_eq!(read(res).await, (StatusCode::OK, "hello 10.0.0.1:1234".to_string()));
+
+        let req = hyper::Request::get("/missing").body(hyper::Body::empty()).unwrap();
+        let res = service.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn wraps_the_server_in_tower_layers() {
+        use hyper::header::HeaderValue;
+        use tower::ServiceBuilder;
+
+        let mut server = Nickel::new();
+        server.layer(ServiceBuilder::new()
+                     .map_response(|mut res: hyper::Response<String>| {
+                         res.headers_mut().insert("x-layer", HeaderValue::from_static("outer"));
+                         res
+                     })
+                     .map_response(|res: hyper::Response<hyper::Body>| {
+                         // Layers may change the body type
+                         res.map(|_| String::from("replaced"))
+                     }));
+        server.get("/hello", middleware!("hello"));
+        let server = serve(server).await;
+
+        let res = server.fetch("/hello").await;
+        assert_eq!(res.headers()["x-layer"], "outer");
+        assert_eq!(read(res).await.1, "replaced");
+    }
+}
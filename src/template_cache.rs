@@ -34,6 +34,7 @@ impl TemplateEntry {
 /// ReloadPolicy controls how often the modification time of template
 /// file is checked. Checks take place only when rendering the
 /// template.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ReloadPolicy {
     /// Never check for changes. Once loaded, templates will not
     /// change unless one of TemplateCache::clear or
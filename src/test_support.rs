@@ -0,0 +1,114 @@
+//! Helpers for the tests that run a real server.
+use crate::{ListeningServer, MiddlewareResult, Nickel, NickelError, Request, Response};
+use hyper::client::HttpConnector;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Body, Client, StatusCode};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// A server started on a random port. It is shut down when dropped.
+pub(crate) struct TestServer {
+    listening: ListeningServer,
+    base_url: String,
+    client: Client<HttpConnector>,
+}
+
+/// Start `server` on a random local port.
+pub(crate) async fn serve<D: Send + 'static + Sync>(server: Nickel<D>) -> TestServer {
+    let listening = server.start("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listening.local_addr());
+    TestServer { listening, base_url, client: Client::new() }
+}
+
+impl TestServer {
+    pub(crate) fn listening(&self) -> &ListeningServer {
+        &self.listening
+    }
+
+    pub(crate) fn addr(&self) -> SocketAddr {
+        self.listening.local_addr().tcp().unwrap()
+    }
+
+    pub(crate) fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Send `req`, reusing one connection across calls where possible.
+    pub(crate) async fn send(&self, req: hyper::Request<Body>) -> hyper::Response<Body> {
+        self.client.request(req).await.unwrap()
+    }
+
+    pub(crate) async fn fetch(&self, path: &str) -> hyper::Response<Body> {
+        self.send(hyper::Request::get(self.url(path)).body(Body::empty()).unwrap()).await
+    }
+
+    pub(crate) async fn get(&self, path: &str) -> (StatusCode, String) {
+        read(self.fetch(path).await).await
+    }
+
+    pub(crate) async fn post<B: Into<Body>>(&self, path: &str, body: B) -> (StatusCode, String) {
+        let req = hyper::Request::post(self.url(path)).body(body.into()).unwrap();
+        read(self.send(req).await).await
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.listening.shutdown();
+    }
+}
+
+/// The status and body of `res`.
+pub(crate) async fn read(res: hyper::Response<Body>) -> (StatusCode, String) {
+    let status = res.status();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    (status, String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Send a GET request by hand and read the start of the response.
+pub(crate) async fn get_raw<S>(stream: &mut S, path: &str, keep_alive: bool) -> String
+where S: AsyncRead + AsyncWrite + Unpin {
+    let connection = if keep_alive { "keep-alive" } else { "close" };
+    let req = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: {}\r\n\r\n", path, connection);
+    stream.write_all(req.as_bytes()).await.unwrap();
+    let mut buf = vec![0; 1024];
+    let n = stream.read(&mut buf).await.unwrap();
+    String::from_utf8_lossy(&buf[..n]).into_owned()
+}
+
+/// Whether the server closes `stream` within `limit`, without sending more.
+pub(crate) async fn closed_within(stream: &mut TcpStream, limit: Duration) -> bool {
+    let mut buf = [0; 16];
+    match tokio::time::timeout(limit, stream.read(&mut buf)).await {
+        Ok(Ok(0)) | Ok(Err(_)) => true,
+        Ok(Ok(_)) => panic!("unexpected data on idle connection"),
+        Err(_) => false,
+    }
+}
+
+/// Answers "done" after 300ms.
+pub(crate) async fn slow(_: &mut Request, res: Response) -> MiddlewareResult {
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    res.send("done")
+}
+
+pub(crate) async fn fail(_: &mut Request, res: Response) -> MiddlewareResult {
+    res.error(StatusCode::IM_A_TEAPOT, "teapot")
+}
+
+pub(crate) async fn panics(_: &mut Request, _: Response) -> MiddlewareResult {
+    panic!("handler blew up")
+}
+
+pub(crate) async fn lose_response(_: &mut Request, _: Response) -> MiddlewareResult {
+    unsafe { Err(NickelError::without_response("lost the response")) }
+}
+
+/// Sets `x-tagged: yes` and passes the request on.
+pub(crate) async fn tag(_: &mut Request, mut res: Response) -> MiddlewareResult {
+    tokio::task::yield_now().await;
+    res.set_header(HeaderName::from_static("x-tagged"), HeaderValue::from_static("yes"));
+    res.next_middleware()
+}
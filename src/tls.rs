@@ -0,0 +1,111 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+
+/// Certificate chain and private key used to terminate TLS connections.
+///
+/// Only available with the `ssl` feature enabled.
+///
+/// # Examples
+/// ```rust,no_run
+/// use nickel::{Nickel, TlsConfig};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let tls = TlsConfig::from_pem_files("examples/assets/self_signed.crt",
+///                                         "examples/assets/key.pem").unwrap();
+///     let server = Nickel::new();
+///     server.listen_https("127.0.0.1:6767", tls).await.unwrap();
+/// }
+/// ```
+#[derive(Clone)]
+pub struct TlsConfig {
+    certs: Vec<Certificate>,
+    key: PrivateKey,
+}
+
+impl TlsConfig {
+    /// Load a PEM encoded certificate chain and private key from disk.
+    ///
+    /// The key may be PKCS#8, PKCS#1 (RSA) or SEC1 (EC) encoded; the
+    /// first key found in the file is used.
+    pub fn from_pem_files<C, K>(cert_path: C, key_path: K) -> io::Result<TlsConfig>
+    where C: AsRef<Path>, K: AsRef<Path> {
+        let mut cert_reader = BufReader::new(File::open(cert_path)?);
+        let mut key_reader = BufReader::new(File::open(key_path)?);
+        TlsConfig::from_pem(&mut cert_reader, &mut key_reader)
+    }
+
+    /// Read a PEM encoded certificate chain and private key.
+    pub fn from_pem(certs: &mut dyn io::BufRead, key: &mut dyn io::BufRead) -> io::Result<TlsConfig> {
+        let certs: Vec<Certificate> = rustls_pemfile::certs(certs)?
+            .into_iter()
+            .map(Certificate)
+            .collect();
+        if certs.is_empty() {
+            return Err(invalid_data("no certificates found"));
+        }
+
+        let key = loop {
+            match rustls_pemfile::read_one(key)? {
+                Some(rustls_pemfile::Item::PKCS8Key(key)) |
+                Some(rustls_pemfile::Item::RSAKey(key)) |
+                Some(rustls_pemfile::Item::ECKey(key)) => break PrivateKey(key),
+                Some(_) => continue,
+                None => return Err(invalid_data("no private key found")),
+            }
+        };
+
+        Ok(TlsConfig { certs, key })
+    }
+
+    pub(crate) fn acceptor(&self, http2: bool) -> io::Result<TlsAcceptor> {
+        Ok(TlsAcceptor::from(Arc::new(self.server_config(http2)?)))
+    }
+
+    fn server_config(&self, http2: bool) -> io::Result<ServerConfig> {
+        let mut config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(self.certs.clone(), self.key.clone())
+            .map_err(|e| invalid_data(&e.to_string()))?;
+        if http2 {
+            config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        } else {
+            config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        }
+        Ok(config)
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[test]
+fn loads_checked_in_certificate() {
+    let tls = TlsConfig::from_pem_files("examples/assets/self_signed.crt",
+                                        "examples/assets/key.pem").unwrap();
+    assert_eq!(tls.certs.len(), 1);
+    assert!(tls.acceptor(false).is_ok());
+}
+
+#[test]
+fn advertises_h2_only_when_enabled() {
+    let tls = TlsConfig::from_pem_files("examples/assets/self_signed.crt",
+                                        "examples/assets/key.pem").unwrap();
+    let alpn = tls.server_config(true).unwrap().alpn_protocols;
+    assert_eq!(alpn, vec![b"h2".to_vec(), b"http/1.1".to_vec()]);
+    let alpn = tls.server_config(false).unwrap().alpn_protocols;
+    assert_eq!(alpn, vec![b"http/1.1".to_vec()]);
+}
+
+#[test]
+fn rejects_missing_key() {
+    let mut certs = BufReader::new(File::open("examples/assets/self_signed.crt").unwrap());
+    let mut key = BufReader::new(&b""[..]);
+    assert!(TlsConfig::from_pem(&mut certs, &mut key).is_err());
+}
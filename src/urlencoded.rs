@@ -1,8 +1,14 @@
+use encoding_rs::Encoding;
 use groupable::Groupable;
 use hyper::Uri;
+use percent_encoding::percent_decode;
 use std::collections::HashMap;
 use url::form_urlencoded;
 
+mod de;
+
+pub(crate) use self::de::from_params;
+
 type QueryStore = HashMap<String, Vec<String>>;
 
 #[derive(Debug, PartialEq, Eq)]
@@ -41,6 +47,29 @@ pub fn parse_uri(origin: &Uri) -> Params {
     origin.query().map(|q| parse(&*q)).unwrap_or_else(|| Params(HashMap::new()))
 }
 
+/// Collect already decoded pairs of key and value, such as route parameters.
+pub(crate) fn from_pairs<'a, I>(pairs: I) -> Params
+where I: IntoIterator<Item = (&'a str, &'a str)> {
+    Params(pairs.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).group())
+}
+
+/// Parse a form body whose percent-encoded bytes are text in `encoding`.
+pub(crate) fn parse_with_encoding(body: &[u8], encoding: &'static Encoding) -> Params {
+    let decode = |part: &[u8]| {
+        let part = part.iter().map(|&b| if b == b'+' { b' ' } else { b }).collect::<Vec<u8>>();
+        let bytes = percent_decode(&part).collect::<Vec<u8>>();
+        encoding.decode_without_bom_handling(&bytes).0.into_owned()
+    };
+    Params(body.split(|&b| b == b'&')
+           .filter(|pair| !pair.is_empty())
+           .map(|pair| {
+               let mut parts = pair.splitn(2, |&b| b == b'=');
+               let key = parts.next().unwrap_or_default();
+               (decode(key), decode(parts.next().unwrap_or_default()))
+           })
+           .group())
+}
+
 #[test]
 fn parses_encoded_string_with_duplicate_keys() {
     let store = parse("foo=bar&message=hello&message=world");
@@ -74,6 +103,17 @@ fn parses_urlencoded_characters() {
     assert_eq!(map.get("message"), Some(&vec!["hello world".to_string()]));
 }
 
+#[test]
+fn parses_bodies_in_other_charsets() {
+    let store = parse_with_encoding(b"name=Ren%E9e+Dupont&city=Z%FCrich&city=", encoding_rs::WINDOWS_1252);
+    assert_eq!(store.get("name"), Some("Renée Dupont"));
+    assert_eq!(store.all("city"), Some(&["Zürich".to_string(), "".to_string()][..]));
+
+    let store = parse_with_encoding(b"name=Ren%C3%A9e&flag", encoding_rs::UTF_8);
+    assert_eq!(store.get("name"), Some("Renée"));
+    assert_eq!(store.get("flag"), Some(""));
+}
+
 #[test]
 fn splits_and_parses_an_url() {
     let t = |url| {
@@ -0,0 +1,430 @@
+//! Deserializing `Params` into serde types.
+//!
+//! Keys in bracket notation, such as `filter[status]`, are nested into maps,
+//! and a key given more than once, or as `tags[]`, deserializes into a
+//! sequence. Values are parsed into numbers and booleans as the target type
+//! asks for them.
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess,
+                SeqAccess, Visitor};
+use serde::forward_to_deserialize_any;
+use std::collections::{btree_map, BTreeMap};
+use std::fmt;
+use std::str::FromStr;
+use std::vec;
+use super::Params;
+
+/// Deserialize `params` into `T`.
+pub(crate) fn from_params<T: DeserializeOwned>(params: &Params) -> Result<T, Error> {
+    let mut root = BTreeMap::new();
+    for (key, values) in params.map() {
+        let path = split_key(key);
+        insert(&mut root, &path, values)?;
+    }
+    T::deserialize(NodeDeserializer(Node::Map(root)))
+}
+
+/// Why `Params` couldn't be deserialized, with the key of the offending
+/// field when there is one.
+#[derive(Debug)]
+pub(crate) struct Error {
+    path: Vec<String>,
+    message: String,
+}
+
+impl Error {
+    fn in_field(mut self, key: &str) -> Error {
+        self.path.insert(0, key.to_string());
+        self
+    }
+
+    /// The key of the offending field, in bracket notation.
+    pub(crate) fn field(&self) -> Option<String> {
+        let (first, rest) = self.path.split_first()?;
+        Some(rest.iter().fold(first.clone(), |key, segment| format!("{}[{}]", key, segment)))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.field() {
+            Some(field) => write!(f, "invalid value for '{}': {}", field, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error { path: Vec::new(), message: msg.to_string() }
+    }
+}
+
+enum Node<'a> {
+    Values(Vec<&'a str>),
+    Map(BTreeMap<&'a str, Node<'a>>),
+}
+
+// The most bracketed segments a key may nest, as serde_qs allows. Nesting
+// is recursive, so deeper keys would let a client overflow the stack.
+const MAX_DEPTH: usize = 5;
+
+// `filter[status][]` becomes `["filter", "status"]`, a trailing `[]` only
+// marks the key as a list. Keys that aren't well formed, or nest more than
+// `MAX_DEPTH` deep, are used as is.
+fn split_key(key: &str) -> Vec<&str> {
+    let (name, mut rest) = match key.find('[') {
+        Some(0) | None => return vec![key],
+        Some(i) => key.split_at(i),
+    };
+    let mut path = vec![name];
+    while !rest.is_empty() {
+        let segment = rest.strip_prefix('[')
+            .and_then(|rest| rest.find(']').map(|end| (&rest[..end], &rest[end + 1..])));
+        match segment {
+            Some(("", "")) => break,
+            Some((segment, remaining)) if !segment.is_empty() && path.len() <= MAX_DEPTH => {
+                path.push(segment);
+                rest = remaining;
+            },
+            _ => return vec![key],
+        }
+    }
+    path
+}
+
+fn insert<'a>(map: &mut BTreeMap<&'a str, Node<'a>>,
+              path: &[&'a str],
+              values: &'a [String]) -> Result<(), Error> {
+    let (key, rest) = path.split_first().expect("split_key returns at least one segment");
+    let conflict = || Error {
+        path: vec![key.to_string()],
+        message: "given both as a value and with nested keys".to_string(),
+    };
+    if rest.is_empty() {
+        match map.entry(key).or_insert_with(|| Node::Values(Vec::new())) {
+            Node::Values(existing) => existing.extend(values.iter().map(|v| &v[..])),
+            Node::Map(_) => return Err(conflict()),
+        }
+    } else {
+        match map.entry(key).or_insert_with(|| Node::Map(BTreeMap::new())) {
+            Node::Map(nested) => insert(nested, rest, values).map_err(|e| e.in_field(key))?,
+            Node::Values(_) => return Err(conflict()),
+        }
+    }
+    Ok(())
+}
+
+struct NodeDeserializer<'a>(Node<'a>);
+
+// Scalars take the first of the values given for a key, as `Params::get`
+// does.
+macro_rules! forward_to_single {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                self.single()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for NodeDeserializer<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Node::Map(map) => visitor.visit_map(Entries { iter: map.into_iter(), value: None }),
+            Node::Values(values) if values.len() > 1 => visitor.visit_seq(Values(values.into_iter())),
+            Node::Values(values) => ValueDeserializer(values[0]).deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Node::Values(values) => visitor.visit_seq(Values(values.into_iter())),
+            // `ids[0]=4&ids[1]=2`
+            Node::Map(map) => {
+                let mut items = map.into_iter()
+                    .map(|(key, node)| key.parse::<usize>().map(|i| (i, key, node)))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| de::Error::custom("expected a list, found nested keys"))?;
+                items.sort_by_key(|&(i, _, _)| i);
+                visitor.visit_seq(Items(items.into_iter()))
+            },
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Node::Values(ref values) if values.iter().all(|v| v.is_empty()) => visitor.visit_none(),
+            node => visitor.visit_some(NodeDeserializer(node)),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+                                                  -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize, visitor: V)
+                                                -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        map struct identifier ignored_any
+    }
+
+    forward_to_single! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char deserialize_str
+        deserialize_string deserialize_bytes deserialize_byte_buf deserialize_unit
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V)
+                                               -> Result<V::Value, Error> {
+        self.single()?.deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str, variants: &'static [&'static str],
+                                         visitor: V) -> Result<V::Value, Error> {
+        self.single()?.deserialize_enum(name, variants, visitor)
+    }
+}
+
+impl<'a> NodeDeserializer<'a> {
+    fn single(self) -> Result<ValueDeserializer<'a>, Error> {
+        match self.0 {
+            Node::Values(values) => Ok(ValueDeserializer(values[0])),
+            Node::Map(_) => Err(de::Error::custom("expected a value, found nested keys")),
+        }
+    }
+}
+
+struct ValueDeserializer<'a>(&'a str);
+
+impl<'a> ValueDeserializer<'a> {
+    fn parse<T>(&self) -> Result<T, Error>
+    where T: FromStr, T::Err: fmt::Display {
+        self.0.parse().map_err(de::Error::custom)
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                visitor.$visit(self.parse()?)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for ValueDeserializer<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_str(self.0)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            "true" | "on" | "1" => visitor.visit_bool(true),
+            "false" | "off" | "0" => visitor.visit_bool(false),
+            other => Err(de::Error::custom(format_args!("expected a boolean, found '{}'", other))),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.0.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+                                               -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+                                                  -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str, variants: &'static [&'static str],
+                                         visitor: V) -> Result<V::Value, Error> {
+        IntoDeserializer::<Error>::into_deserializer(self.0).deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct Entries<'a> {
+    iter: btree_map::IntoIter<&'a str, Node<'a>>,
+    value: Option<(&'a str, Node<'a>)>,
+}
+
+impl<'de, 'a> MapAccess<'de> for Entries<'a> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        match self.iter.next() {
+            Some((key, node)) => {
+                self.value = Some((key, node));
+                seed.deserialize(ValueDeserializer(key)).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let (key, node) = self.value.take().expect("next_value_seed called before next_key_seed");
+        seed.deserialize(NodeDeserializer(node)).map_err(|e| e.in_field(key))
+    }
+}
+
+struct Values<'a>(vec::IntoIter<&'a str>);
+
+impl<'de, 'a> SeqAccess<'de> for Values<'a> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+        self.0.next().map(|value| seed.deserialize(ValueDeserializer(value))).transpose()
+    }
+}
+
+struct Items<'a>(vec::IntoIter<(usize, &'a str, Node<'a>)>);
+
+impl<'de, 'a> SeqAccess<'de> for Items<'a> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+        self.0.next()
+            .map(|(_, key, node)| seed.deserialize(NodeDeserializer(node)).map_err(|e| e.in_field(key)))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_derive::Deserialize;
+    use std::collections::HashMap;
+    use super::from_params;
+    use crate::urlencoded::parse;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Filter {
+        status: String,
+        assignee: Option<String>,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Order { Newest, Oldest }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Search {
+        page: Option<u32>,
+        #[serde(default)]
+        tag: Vec<String>,
+        closed: bool,
+        order: Option<Order>,
+        filter: Option<Filter>,
+    }
+
+    #[test]
+    fn deserializes_repeated_and_nested_keys() {
+        let params = parse("page=2&tag=bug&tag=ui&closed=on&order=oldest\
+                            &filter[status]=open&filter[assignee]=");
+        assert_eq!(from_params::<Search>(&params).unwrap(), Search {
+            page: Some(2),
+            tag: vec!["bug".to_string(), "ui".to_string()],
+            closed: true,
+            order: Some(Order::Oldest),
+            filter: Some(Filter { status: "open".to_string(), assignee: None }),
+        });
+
+        let params = parse("closed=false&page=&tag[]=bug");
+        assert_eq!(from_params::<Search>(&params).unwrap(), Search {
+            page: None,
+            tag: vec!["bug".to_string()],
+            closed: false,
+            order: None,
+            filter: None,
+        });
+
+        let params = parse("ids[1]=7&ids[0]=4&ids[10]=9");
+        let map: HashMap<String, Vec<u8>> = from_params(&params).unwrap();
+        assert_eq!(map["ids"], vec![4, 7, 9]);
+        assert!(from_params::<HashMap<String, Vec<u8>>>(&parse("ids[a]=1")).is_err());
+    }
+
+    #[test]
+    fn deeply_nested_keys_are_used_as_is() {
+        let params = parse("a[b][c][d][e][f]=deep");
+        let map: HashMap<String, HashMap<String, HashMap<String, HashMap<String,
+                 HashMap<String, HashMap<String, String>>>>>> = from_params(&params).unwrap();
+        assert_eq!(map["a"]["b"]["c"]["d"]["e"]["f"], "deep");
+
+        let key = format!("x{}", "[b]".repeat(100_000));
+        let params = parse(&format!("{}=deep&y[b]=1", key));
+        let map: HashMap<String, Node> = from_params(&params).unwrap();
+        assert_eq!(map[&key], Node::Value("deep".to_string()));
+
+        let params = parse("a[b][c][d][e][f][g]=deep");
+        let map: HashMap<String, String> = from_params(&params).unwrap();
+        assert_eq!(map["a[b][c][d][e][f][g]"], "deep");
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(untagged)]
+    enum Node {
+        Value(String),
+        Map(HashMap<String, String>),
+    }
+
+    #[test]
+    fn errors_name_the_field() {
+        let message = |query| from_params::<Search>(&parse(query)).unwrap_err().to_string();
+        assert_eq!(message("closed=1&page=two"),
+                   "invalid value for 'page': invalid digit found in string");
+        assert_eq!(message("closed=maybe"),
+                   "invalid value for 'closed': expected a boolean, found 'maybe'");
+        assert_eq!(message("closed=1&filter[assignee]=me"),
+                   "invalid value for 'filter': missing field `status`");
+        assert_eq!(message("page=1"), "missing field `closed`");
+        assert_eq!(message("closed=1&filter=open&filter[status]=open"),
+                   "invalid value for 'filter': given both as a value and with nested keys");
+    }
+}
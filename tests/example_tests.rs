@@ -1,3 +1,7 @@
+// The examples are handed their listening socket the way a supervisor doing
+// socket activation would, which is only supported on unix.
+#![cfg(unix)]
+
 // HACK: integration_testing example refers to `nickel::foo`
 // and this import helps that resolve rather than requiring `self::nickel::foo`
 // which is an oddity due to the include method, which is used as tests in examples
@@ -49,3 +49,23 @@ fn post_without_content_type() {
         assert_eq!(res.status(), StatusCode::BAD_REQUEST);
     })
 }
+
+#[test]
+fn post_with_files() {
+    run_example("form_data", |port| {
+        let url = format!("http://localhost:{}/upload", port);
+        let body = "--boundary\r\n\
+                    Content-Disposition: form-data; name=\"attachment\"; filename=\"notes.txt\"\r\n\
+                    Content-Type: text/plain\r\n\r\n\
+                    Call John\r\n\
+                    --boundary--\r\n";
+        let res = Client::new()
+            .post(&url)
+            .header(CONTENT_TYPE, "multipart/form-data; boundary=boundary")
+            .body(body)
+            .send()
+            .unwrap();
+        let s = read_body_to_string(res);
+        assert!(s.contains(r#"notes.txt (9 bytes)"#), "response didn't have an expected data");
+    })
+}
@@ -1,42 +1,38 @@
-use std::sync::Arc;
+use reqwest::blocking::Client;
+use reqwest::Certificate;
+use std::fs;
 
-use hyper::net::{HttpsConnector,Openssl};
-use hyper::client::Client;
-
-use util::{run_example, read_body_to_string};
+use crate::util::{run_example, read_body_to_string};
 
 #[test]
 fn https() {
     run_example("https", |port| {
         let paths = ["", "foo", "bar.html", "foo-barrrr/baz"];
 
-        let context = create_context();
+        let client = create_client();
         for path in &paths {
             let url = format!("https://localhost:{}/{}", port, path);
-            // Cannot use read_url() with SSL
-            let s = read_url_https(&url, context.clone());
+            let res = client.get(&url).send().unwrap();
+            let s = read_body_to_string(res);
 
             assert_eq!(s, "Hello World from HTTPS");
         }
     })
 }
 
-fn create_context() -> Openssl {
-    let mut openssl = Openssl::default();
-
-    // Trick so we don't depend on the crate openssl directly
-    Arc::get_mut(&mut openssl.context)
-        .unwrap()
-        .set_CA_file("examples/assets/self_signed.crt")
-        .unwrap();
-    openssl
+#[test]
+fn rejects_plain_http() {
+    run_example("https", |port| {
+        let url = format!("http://localhost:{}/", port);
+        assert!(Client::new().get(&url).send().is_err());
+    })
 }
 
-fn read_url_https(url: &str, ssl: Openssl) -> String {
-    let connector = HttpsConnector::new(ssl);
-    let client = Client::with_connector(connector);
-    let mut res = client.get(url)
-                        .send()
-                        .unwrap();
-    read_body_to_string(&mut res)
+fn create_client() -> Client {
+    let pem = fs::read("examples/assets/self_signed.crt").unwrap();
+    let cert = Certificate::from_pem(&pem).unwrap();
+    Client::builder()
+        .add_root_certificate(cert)
+        .build()
+        .unwrap()
 }
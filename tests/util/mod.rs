@@ -3,8 +3,11 @@ use reqwest::blocking::{get, Client, Response};
 
 use std::collections::HashSet;
 use std::process::{Child, Command, Stdio};
-use std::{env, thread};
-use std::io::{BufReader, BufRead, Read};
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
 use std::sync::Mutex;
 
 struct Bomb(Child);
@@ -16,14 +19,6 @@ impl Drop for Bomb {
             Ok(()) => {},
             Err(e) => panic!("Leaking child process: {:?}", e)
         }
-
-        if thread::panicking() {
-            let mut s = String::new();
-            let stdout = self.0.stdout.as_mut().unwrap();
-            stdout.read_to_string(&mut s).unwrap();
-
-            println!("Unparsed Stdout:\n{}", s);
-        }
     }
 }
 
@@ -52,41 +47,69 @@ pub fn read_url(url: &str) -> String {
 
 pub fn run_example<F>(name: &str, f: F)
 where F: FnOnce(u16) {
-    cargo_build(name);
-
-    let command = format!("target/debug/examples/{}", name);
-    let child = Command::new(&command)
-        .env("NICKEL_TEST_HARNESS", "1")
-        .stdout(Stdio::piped())
-        .spawn()
-        .unwrap();
-
-    //thread::sleep(time::Duration::from_secs(5));
-    let mut bomb = Bomb(child);
-    let port = parse_port(&mut bomb);
-
-    f(port);
+    spawn_example(name, None, f)
 }
 
 pub fn run_example_with_env<F>(name: &str, env_var: &str, env_val: &str, f: F)
+where F: FnOnce(u16) {
+    spawn_example(name, Some((env_var, env_val)), f)
+}
+
+fn spawn_example<F>(name: &str, env: Option<(&str, &str)>, f: F)
 where F: FnOnce(u16) {
     cargo_build(name);
 
+    // Bind a random port here and hand the socket to the example, the same
+    // way a supervisor doing socket activation would. The socket is already
+    // listening, so there is no need to wait for the example to start up.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
     let command = format!("target/debug/examples/{}", name);
-    let child = Command::new(&command)
-        .env("NICKEL_TEST_HARNESS", "1")
-        .env(env_var, env_val)
-        .stdout(Stdio::piped())
+    let mut command = activated_command(&command, &listener);
+    if let Some((key, val)) = env {
+        command.env(key, val);
+    }
+    let child = command.stdout(Stdio::null())
         .spawn()
         .unwrap();
+    drop(listener);
 
-    //thread::sleep(time::Duration::from_secs(5));
-    let mut bomb = Bomb(child);
-    let port = parse_port(&mut bomb);
+    let _bomb = Bomb(child);
 
     f(port);
 }
 
+// Runs `program` with `listener` as its first passed file descriptor.
+// `LISTEN_PID` has to name the example's own process, so it is set by a
+// shell that then execs the example in its place.
+fn activated_command(program: &str, listener: &TcpListener) -> Command {
+    let fd = listener.as_raw_fd();
+    let mut command = Command::new("sh");
+    command.arg("-c")
+        .arg("LISTEN_PID=$$ exec \"$0\"")
+        .arg(program)
+        .env("LISTEN_FDS", "1");
+
+    // Safety: only async-signal-safe calls between fork and exec. `dup2`
+    // leaves the new descriptor without close-on-exec so it survives, but
+    // is a no-op if the listener already happens to be descriptor 3.
+    unsafe {
+        command.pre_exec(move || {
+            let result = if fd == 3 {
+                libc::fcntl(fd, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, 3)
+            };
+            if result == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    command
+}
+
 // We cannot use `cargo run --example foo` as when a test fails
 // we can only send SIGKILL, which cargo doesn't propogate to the
 // child process. Rust currently doesn't seem to give us a way to
@@ -110,12 +133,16 @@ fn cargo_build(name: &str) {
 
     let mut command = Command::new("cargo");
 
-    command.env("NICKEL_TEST_HARNESS", "1")
-           .stdout(Stdio::piped())
+    command.stdout(Stdio::piped())
            .arg("build")
            .arg("--example")
            .arg(name);
 
+    // build the example with the same optional features as the test suite
+    if cfg!(feature = "ssl") {
+        command.arg("--features").arg("ssl");
+    }
+
     // support for features passed in the env (as we do on travis)
     if let Some(arg) = env::var("FEATURES").ok() {
         for feature_arg in arg.split_whitespace() {
@@ -130,22 +157,3 @@ fn cargo_build(name: &str) {
 lazy_static! {
     static ref BUILD_LOCK : Mutex<HashSet<String>> = Mutex::new(HashSet::new());
 }
-
-fn parse_port(&mut Bomb(ref mut process): &mut Bomb) -> u16 {
-    let stdout = BufReader::new(process.stdout.as_mut().unwrap());
-
-    let line = stdout.lines()
-        .map(Result::unwrap)
-        .inspect(|line| println!("Processing Stdout: {:?}", line))
-        .filter(|line| line.starts_with("Listening"))
-        .next()
-        .expect("Didn't find a line from stdout");
-
-    let port = line.rsplitn(2, ':')
-        .next()
-        .and_then(|s| s.parse().ok())
-        .expect(&format!("Failed to parse port from {:?}", line));
-
-    println!("Parsed: port={} from {:?}", port, line);
-    port
-}